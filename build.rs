@@ -0,0 +1,46 @@
+use std::{
+    path::Path,
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Embeds the git commit and build time, served from `/version`.
+fn main() {
+    // Docker builds don't see `.git`, so the SHA can be passed in instead.
+    let git_sha = std::env::var("GIT_SHA")
+        .ok()
+        .filter(|sha| !sha.is_empty())
+        .or_else(|| {
+            let output = Command::new("git")
+                .args(["rev-parse", "HEAD"])
+                .output()
+                .ok()?;
+            output
+                .status
+                .success()
+                .then(|| String::from_utf8_lossy(&output.stdout).trim().to_owned())
+        })
+        .unwrap_or_else(|| "unknown".to_owned());
+
+    let build_timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("the clock should be past 1970")
+        .as_secs();
+
+    println!("cargo:rustc-env=CATSCII_GIT_SHA={git_sha}");
+    println!("cargo:rustc-env=CATSCII_BUILD_TIMESTAMP={build_timestamp}");
+    println!("cargo:rerun-if-env-changed=GIT_SHA");
+    // HEAD only changes when switching branches, commits move the branch it
+    // points to, which lives in its own file until `git gc` packs it. Files
+    // that don't exist would rerun this on every build, so they're skipped.
+    let mut watched = vec![".git/HEAD".to_owned(), ".git/packed-refs".to_owned()];
+    if let Some(branch) = std::fs::read_to_string(".git/HEAD")
+        .ok()
+        .and_then(|head| Some(head.strip_prefix("ref: ")?.trim().to_owned()))
+    {
+        watched.push(format!(".git/{branch}"));
+    }
+    for path in watched.iter().filter(|path| Path::new(path).exists()) {
+        println!("cargo:rerun-if-changed={path}");
+    }
+}
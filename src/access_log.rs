@@ -0,0 +1,142 @@
+use axum::{
+    extract::{ConnectInfo, State},
+    http::Request,
+    middleware::Next,
+    response::Response,
+};
+use opentelemetry::trace::TraceId;
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+use tracing::info;
+
+use crate::client_ip::client_ip;
+
+tokio::task_local! {
+    /// Filled in by the handler's request span, if it starts one.
+    static TRACE_ID: Arc<Mutex<Option<TraceId>>>;
+}
+
+/// Remembers `trace_id` for the access log line of the current request.
+pub fn record_trace_id(trace_id: TraceId) {
+    if trace_id == TraceId::INVALID {
+        return;
+    }
+    // Outside of a request (`--once`, streams) there's nothing to log to.
+    _ = TRACE_ID.try_with(|slot| *slot.lock().unwrap() = Some(trace_id));
+}
+
+/// The trace of the current request, or [`TraceId::INVALID`] if its handler
+/// hasn't started one.
+pub fn recorded_trace_id() -> TraceId {
+    TRACE_ID
+        .try_with(|slot| *slot.lock().unwrap())
+        .ok()
+        .flatten()
+        .unwrap_or(TraceId::INVALID)
+}
+
+/// Logs one line per request, with its trace for the routes that have one.
+pub async fn access_log<B>(
+    State(trusted_proxy_hops): State<usize>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let client_ip = client_ip(request.headers(), peer, trusted_proxy_hops);
+    let method = request.method().clone();
+    let path = request.uri().path().to_owned();
+    let start = Instant::now();
+
+    let trace_id = Arc::new(Mutex::new(None));
+    let response = TRACE_ID.scope(trace_id.clone(), next.run(request)).await;
+
+    let trace_id = trace_id
+        .lock()
+        .unwrap()
+        .map(|trace_id| trace_id.to_string())
+        .unwrap_or_default();
+    info!(
+        method = %method,
+        path = %path,
+        client_ip = %client_ip,
+        status = response.status().as_u16(),
+        latency_ms = start.elapsed().as_secs_f64() * 1000.0,
+        trace_id = %trace_id,
+        "request"
+    );
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{middleware, routing::get, Router};
+
+    /// Keeps whatever the subscriber writes.
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn logs_a_line_per_request() {
+        let captured = Captured::default();
+        let subscriber = tracing_subscriber::fmt()
+            .json()
+            .with_writer({
+                let captured = captured.clone();
+                move || captured.clone()
+            })
+            .finish();
+        // The server runs on this test's single thread, so this sees it.
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let trace_id = TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap();
+        let router = Router::new()
+            .route(
+                "/cat",
+                get(move || async move {
+                    record_trace_id(trace_id);
+                    "meow"
+                }),
+            )
+            .layer(middleware::from_fn_with_state(0, access_log));
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(router.into_make_service_with_connect_info::<SocketAddr>()),
+        );
+        let response = reqwest::get(format!("http://{addr}/cat?mood=hungry"))
+            .await
+            .unwrap();
+        assert_eq!(response.text().await.unwrap(), "meow");
+
+        let output = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+        let fields = output
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .find(|line| line["fields"]["message"] == "request")
+            .expect("a request line")["fields"]
+            .clone();
+        assert_eq!(fields["method"], "GET");
+        assert_eq!(fields["path"], "/cat");
+        assert_eq!(fields["client_ip"], "127.0.0.1");
+        assert_eq!(fields["status"], 200);
+        assert!(fields["latency_ms"].as_f64().unwrap() >= 0.0);
+        assert_eq!(fields["trace_id"], trace_id.to_string());
+    }
+}
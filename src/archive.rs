@@ -0,0 +1,36 @@
+use std::{
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tracing::warn;
+
+/// Writes a source image and the art made from it to `dir`, in the
+/// background so the request doesn't wait on the disk. Failures are logged
+/// and otherwise ignored, the archive is a nice-to-have.
+pub fn spawn_archive(dir: PathBuf, image: Vec<u8>, art: String, art_extension: &'static str) {
+    tokio::spawn(async move {
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        // Concurrent requests can land in the same millisecond.
+        let stem = format!("{millis}-{:04x}", rand::random::<u16>());
+        let image_extension = image::guess_format(&image)
+            .ok()
+            .and_then(|format| format.extensions_str().first().copied())
+            .unwrap_or("bin");
+
+        let files = [
+            (dir.join(format!("{stem}.{image_extension}")), image),
+            (
+                dir.join(format!("{stem}.{art_extension}")),
+                art.into_bytes(),
+            ),
+        ];
+        for (path, contents) in files {
+            if let Err(e) = tokio::fs::write(&path, contents).await {
+                warn!("Failed to archive {}: {e}", path.display());
+            }
+        }
+    });
+}
@@ -0,0 +1,126 @@
+use axum::{
+    extract::State,
+    http::{header, HeaderValue, Request, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use opentelemetry::trace::TraceId;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+
+use crate::config::Credentials;
+
+/// HTTP Basic auth against a single user. Without credentials, everyone is
+/// let through.
+pub struct BasicAuth {
+    /// Digest of the credentials token we expect, precomputed. Comparing
+    /// digests keeps the token's length out of response times too.
+    expected: Option<[u8; 32]>,
+}
+
+impl BasicAuth {
+    pub fn new(credentials: Option<&Credentials>) -> Self {
+        Self {
+            expected: credentials.map(|credentials| {
+                let token =
+                    STANDARD.encode(format!("{}:{}", credentials.user, credentials.password));
+                Sha256::digest(token).into()
+            }),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.expected.is_some()
+    }
+
+    fn allows(&self, authorization: Option<&[u8]>) -> bool {
+        match (&self.expected, authorization) {
+            (None, _) => true,
+            (Some(expected), Some(given)) => match basic_token(given) {
+                Some(token) => constant_time_eq(expected, &Sha256::digest(token).into()),
+                None => false,
+            },
+            (Some(_), None) => false,
+        }
+    }
+}
+
+/// The token of a `Basic` authorization, whose scheme is case-insensitive.
+fn basic_token(authorization: &[u8]) -> Option<&[u8]> {
+    let space = authorization.iter().position(|&b| b == b' ')?;
+    let (scheme, token) = authorization.split_at(space);
+    scheme
+        .eq_ignore_ascii_case(b"basic")
+        .then(|| token.trim_ascii_start())
+}
+
+/// Compares without bailing out at the first difference, so response times
+/// don't give away how much of a guess was right.
+fn constant_time_eq(a: &[u8; 32], b: &[u8; 32]) -> bool {
+    a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Middleware answering `401 Unauthorized` unless the request carries the
+/// right credentials.
+pub async fn require_basic_auth<B>(
+    State(auth): State<Arc<BasicAuth>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let authorization = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .map(|h| h.as_bytes());
+    if auth.allows(authorization) {
+        next.run(request).await
+    } else {
+        let mut response = crate::error_body(
+            StatusCode::UNAUTHORIZED,
+            "Unauthorized",
+            TraceId::INVALID,
+            request.headers(),
+        );
+        response.headers_mut().insert(
+            header::WWW_AUTHENTICATE,
+            HeaderValue::from_static(r#"Basic realm="catscii""#),
+        );
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn auth() -> BasicAuth {
+        BasicAuth::new(Some(&Credentials {
+            user: "admin".to_owned(),
+            password: "hunter2".to_owned(),
+        }))
+    }
+
+    #[test]
+    fn allows_the_right_credentials_only() {
+        // base64("admin:hunter2")
+        assert!(auth().allows(Some(b"Basic YWRtaW46aHVudGVyMg==")));
+        assert!(!auth().allows(Some(b"Basic YWRtaW46aHVudGVyMw==")));
+        assert!(!auth().allows(Some(b"Bearer YWRtaW46aHVudGVyMg==")));
+        assert!(!auth().allows(Some(b"Basic")));
+        assert!(!auth().allows(None));
+    }
+
+    #[test]
+    fn matches_the_scheme_case_insensitively() {
+        assert!(auth().allows(Some(b"basic YWRtaW46aHVudGVyMg==")));
+        assert!(auth().allows(Some(b"BASIC  YWRtaW46aHVudGVyMg==")));
+    }
+
+    #[test]
+    fn lets_everyone_through_without_credentials() {
+        let open = BasicAuth::new(None);
+        assert!(!open.is_enabled());
+        assert!(open.allows(None));
+        assert!(open.allows(Some(b"Basic anything")));
+    }
+}
@@ -0,0 +1,78 @@
+use image::{imageops::FilterType, DynamicImage};
+
+/// Bit of each dot in a Braille cell, by column then row.
+const DOTS: [[u32; 4]; 2] = [[0x01, 0x02, 0x04, 0x40], [0x08, 0x10, 0x20, 0x80]];
+
+/// Draws `image` `columns` characters wide with Braille patterns, each of
+/// which packs a 2x4 grid of dots. Dots are lit where the image is brighter
+/// than average, or darker with `invert`. Rows that would take the art past
+/// `max_chars` are cut off before anything gets resized.
+pub fn render(image: &DynamicImage, columns: u32, invert: bool, max_chars: usize) -> String {
+    // Cells are about twice as tall as they are wide, just like their dot
+    // grids, so scaling pixels to dots keeps the proportions.
+    let width = columns * 2;
+    let max_rows = (max_chars / (columns as usize + 1)).clamp(1, u32::MAX as usize / 4);
+    let height = (image.height() as u64 * width as u64 / image.width().max(1) as u64)
+        .min(max_rows as u64 * 4) as u32;
+    let height = height.max(4) / 4 * 4;
+    let luma = image
+        .resize_exact(width, height, FilterType::Triangle)
+        .into_luma8();
+
+    let mean = luma.pixels().map(|p| p.0[0] as u64).sum::<u64>() / luma.len().max(1) as u64;
+    let lit = |x: u32, y: u32| (luma.get_pixel(x, y).0[0] as u64 > mean) != invert;
+
+    let mut art = String::with_capacity(((columns + 1) * height / 4 * 3) as usize);
+    for cell_y in (0..height).step_by(4) {
+        for cell_x in (0..width).step_by(2) {
+            let mut bits = 0;
+            for (dx, column) in DOTS.iter().enumerate() {
+                for (dy, bit) in column.iter().enumerate() {
+                    if lit(cell_x + dx as u32, cell_y + dy as u32) {
+                        bits |= bit;
+                    }
+                }
+            }
+            art.push(char::from_u32(0x2800 + bits).expect("Braille patterns are valid chars"));
+        }
+        art.push('\n');
+    }
+    art
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GrayImage, Luma};
+
+    /// White on the left half, black on the right.
+    fn half_white(width: u32, height: u32) -> DynamicImage {
+        DynamicImage::ImageLuma8(GrayImage::from_fn(width, height, |x, _| {
+            Luma([if x < width / 2 { 255 } else { 0 }])
+        }))
+    }
+
+    #[test]
+    fn draws_columns_wide_rows_of_cells() {
+        let art = render(&half_white(40, 40), 10, false, usize::MAX);
+        let rows: Vec<_> = art.lines().collect();
+        // 20x20 dots, so 5 rows of 10 cells.
+        assert_eq!(rows.len(), 5);
+        assert!(rows.iter().all(|row| row.chars().count() == 10));
+    }
+
+    #[test]
+    fn lights_the_brighter_dots() {
+        let art = render(&half_white(8, 8), 2, false, usize::MAX);
+        assert_eq!(art, "⣿⠀\n");
+        let inverted = render(&half_white(8, 8), 2, true, usize::MAX);
+        assert_eq!(inverted, "⠀⣿\n");
+    }
+
+    #[test]
+    fn tall_images_stop_at_max_chars() {
+        let art = render(&half_white(2, 100_000), 10, false, 55);
+        // 11 characters a row, newline included.
+        assert_eq!(art.lines().count(), 5);
+    }
+}
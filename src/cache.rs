@@ -0,0 +1,221 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    hash::Hash,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// A [`BoundedCache`] whose entries also expire `ttl` after they were
+/// inserted.
+pub struct TtlCache<K, V> {
+    ttl: Duration,
+    entries: BoundedCache<K, (Instant, V)>,
+}
+
+impl<K, V> TtlCache<K, V>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+{
+    pub fn new(ttl: Duration, capacity: usize) -> Self {
+        Self {
+            ttl,
+            entries: BoundedCache::new(capacity),
+        }
+    }
+
+    /// Returns a copy of the value for `key`, if it hasn't expired yet.
+    pub fn get(&self, key: &K) -> Option<V> {
+        let (inserted_at, value) = self.entries.get(key)?;
+        if inserted_at.elapsed() < self.ttl {
+            Some(value)
+        } else {
+            self.entries.remove(key);
+            None
+        }
+    }
+
+    pub fn insert(&self, key: K, value: V) {
+        self.entries.insert(key, (Instant::now(), value));
+    }
+
+    /// Entries held, expired ones that haven't been evicted yet included.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+/// A map holding at most `capacity` entries, evicting the least recently
+/// used one once full. Optionally also bounded by the total weight of its
+/// values, see [`BoundedCache::with_max_weight`].
+pub struct BoundedCache<K, V> {
+    capacity: usize,
+    max_weight: usize,
+    weigh: fn(&V) -> usize,
+    inner: Mutex<BoundedCacheInner<K, V>>,
+}
+
+struct BoundedCacheInner<K, V> {
+    entries: HashMap<K, V>,
+    /// Least recently used first.
+    recency: VecDeque<K>,
+    weight: usize,
+}
+
+impl<K, V> BoundedCache<K, V>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+{
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            max_weight: usize::MAX,
+            weigh: |_| 0,
+            inner: Mutex::new(BoundedCacheInner {
+                entries: Default::default(),
+                recency: Default::default(),
+                weight: 0,
+            }),
+        }
+    }
+
+    /// Also evicts entries while the values weigh more than `max_weight` in
+    /// total, as measured by `weigh`.
+    pub fn with_max_weight(self, max_weight: usize, weigh: fn(&V) -> usize) -> Self {
+        Self {
+            max_weight,
+            weigh,
+            ..self
+        }
+    }
+
+    pub fn get(&self, key: &K) -> Option<V> {
+        let mut inner = self.inner.lock().unwrap();
+        let value = inner.entries.get(key).cloned()?;
+        inner.touch(key);
+        Some(value)
+    }
+
+    pub fn insert(&self, key: K, value: V) {
+        let weight = (self.weigh)(&value);
+        if self.capacity == 0 || weight > self.max_weight {
+            return;
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+        inner.weight += weight;
+        match inner.entries.insert(key.clone(), value) {
+            Some(previous) => {
+                inner.weight -= (self.weigh)(&previous);
+                inner.touch(&key);
+            }
+            None => inner.recency.push_back(key),
+        }
+        while inner.entries.len() > self.capacity || inner.weight > self.max_weight {
+            let Some(oldest) = inner.recency.pop_front() else {
+                break;
+            };
+            if let Some(evicted) = inner.entries.remove(&oldest) {
+                inner.weight -= (self.weigh)(&evicted);
+            }
+        }
+    }
+
+    pub fn remove(&self, key: &K) {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(removed) = inner.entries.remove(key) {
+            inner.weight -= (self.weigh)(&removed);
+            inner.recency.retain(|k| k != key);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().entries.len()
+    }
+
+    /// Total weight of the values held.
+    pub fn weight(&self) -> usize {
+        self.inner.lock().unwrap().weight
+    }
+}
+
+impl<K: Eq, V> BoundedCacheInner<K, V> {
+    /// Marks `key` as the most recently used.
+    fn touch(&mut self, key: &K) {
+        if let Some(position) = self.recency.iter().position(|k| k == key) {
+            if let Some(key) = self.recency.remove(position) {
+                self.recency.push_back(key);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_expire_after_the_ttl() {
+        let cache = TtlCache::new(Duration::from_millis(20), 10);
+        cache.insert("cat", 1);
+        assert_eq!(cache.get(&"cat"), Some(1));
+        std::thread::sleep(Duration::from_millis(30));
+        assert_eq!(cache.get(&"cat"), None);
+        assert_eq!(cache.len(), 0);
+    }
+
+    #[test]
+    fn evicts_the_least_recently_used() {
+        let cache = BoundedCache::new(2);
+        cache.insert("a", 1);
+        cache.insert("b", 2);
+        // Reading `a` makes `b` the oldest.
+        assert_eq!(cache.get(&"a"), Some(1));
+        cache.insert("c", 3);
+        assert_eq!(cache.get(&"b"), None);
+        assert_eq!(cache.get(&"a"), Some(1));
+        assert_eq!(cache.get(&"c"), Some(3));
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn replacing_refreshes_without_growing() {
+        let cache = BoundedCache::new(2);
+        cache.insert("a", 1);
+        cache.insert("b", 2);
+        cache.insert("a", 10);
+        cache.insert("c", 3);
+        assert_eq!(cache.get(&"a"), Some(10));
+        assert_eq!(cache.get(&"b"), None);
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn evicts_until_under_the_max_weight() {
+        let cache = BoundedCache::new(10).with_max_weight(10, Vec::len);
+        cache.insert("a", vec![0; 4]);
+        cache.insert("b", vec![0; 4]);
+        cache.insert("c", vec![0; 4]);
+        assert_eq!(cache.get(&"a"), None);
+        assert_eq!(cache.weight(), 8);
+
+        cache.insert("b", vec![0; 1]);
+        assert_eq!(cache.weight(), 5);
+        cache.remove(&"c");
+        assert_eq!(cache.weight(), 1);
+    }
+
+    #[test]
+    fn skips_values_that_could_never_fit() {
+        let cache = BoundedCache::new(10).with_max_weight(10, Vec::len);
+        cache.insert("small", vec![0; 4]);
+        cache.insert("huge", vec![0; 11]);
+        assert_eq!(cache.get(&"huge"), None);
+        assert_eq!(cache.get(&"small"), Some(vec![0; 4]));
+
+        let disabled = BoundedCache::new(0);
+        disabled.insert("a", 1);
+        assert_eq!(disabled.len(), 0);
+    }
+}
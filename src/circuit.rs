@@ -0,0 +1,150 @@
+use opentelemetry::{trace::get_active_span, KeyValue};
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Stops calling an upstream that keeps failing. After `threshold` failures
+/// in a row calls are refused for `cooldown`, then a single probe is let
+/// through to find out whether it has recovered.
+pub struct CircuitBreaker {
+    threshold: u32,
+    cooldown: Duration,
+    state: Mutex<BreakerState>,
+}
+
+#[derive(Clone, Copy)]
+enum BreakerState {
+    Closed {
+        failures: u32,
+    },
+    Open {
+        until: Instant,
+    },
+    /// A probe is in flight, everyone else keeps being refused until it's
+    /// back. Should the probe never report back, another one is let through
+    /// after `until`.
+    HalfOpen {
+        until: Instant,
+    },
+}
+
+impl BreakerState {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Closed { .. } => "closed",
+            Self::Open { .. } => "open",
+            Self::HalfOpen { .. } => "half_open",
+        }
+    }
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            threshold,
+            cooldown,
+            state: Mutex::new(BreakerState::Closed { failures: 0 }),
+        }
+    }
+
+    /// Whether a call may go through right now.
+    pub fn allow(&self) -> bool {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        match *state {
+            BreakerState::Closed { .. } => true,
+            BreakerState::Open { until } | BreakerState::HalfOpen { until } if now >= until => {
+                let next = BreakerState::HalfOpen {
+                    until: now + self.cooldown,
+                };
+                transition(&mut state, next);
+                true
+            }
+            BreakerState::Open { .. } | BreakerState::HalfOpen { .. } => false,
+        }
+    }
+
+    /// Reports how an allowed call went.
+    pub fn record(&self, success: bool) {
+        let mut state = self.state.lock().unwrap();
+        let next = match (*state, success) {
+            (_, true) => BreakerState::Closed { failures: 0 },
+            (BreakerState::Closed { failures }, false) if failures + 1 < self.threshold => {
+                BreakerState::Closed {
+                    failures: failures + 1,
+                }
+            }
+            (_, false) => BreakerState::Open {
+                until: Instant::now() + self.cooldown,
+            },
+        };
+        transition(&mut state, next);
+    }
+}
+
+/// Moves to `next`, with a span event if that changes anything callers see.
+fn transition(state: &mut BreakerState, next: BreakerState) {
+    let previous = std::mem::replace(state, next);
+    if previous.name() != next.name() {
+        get_active_span(|span| {
+            span.add_event(
+                "circuit_breaker",
+                vec![
+                    KeyValue::new("from", previous.name()),
+                    KeyValue::new("to", next.name()),
+                ],
+            )
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn opens_after_threshold_failures_in_a_row() {
+        let breaker = CircuitBreaker::new(3, Duration::from_secs(60));
+        breaker.record(false);
+        breaker.record(false);
+        assert!(breaker.allow());
+        breaker.record(false);
+        assert!(!breaker.allow());
+    }
+
+    #[test]
+    fn successes_reset_the_count() {
+        let breaker = CircuitBreaker::new(2, Duration::from_secs(60));
+        breaker.record(false);
+        breaker.record(true);
+        breaker.record(false);
+        assert!(breaker.allow());
+    }
+
+    #[test]
+    fn lets_one_probe_through_after_the_cooldown() {
+        let breaker = CircuitBreaker::new(1, Duration::from_millis(20));
+        breaker.record(false);
+        assert!(!breaker.allow());
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(breaker.allow(), "the probe");
+        assert!(!breaker.allow(), "everyone else while the probe is out");
+    }
+
+    #[test]
+    fn the_probe_decides_whether_to_close() {
+        let breaker = CircuitBreaker::new(1, Duration::from_millis(20));
+        breaker.record(false);
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(breaker.allow());
+        breaker.record(false);
+        assert!(!breaker.allow(), "failed probe opens it again");
+
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(breaker.allow());
+        breaker.record(true);
+        assert!(breaker.allow());
+        assert!(breaker.allow());
+    }
+}
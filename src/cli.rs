@@ -0,0 +1,68 @@
+/// Command-line arguments. With none, we run the HTTP server.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct CliArgs {
+    /// Print a single cat to stdout and exit instead of serving.
+    pub once: bool,
+    /// Mirrors the `?width=` query parameter, still unvalidated.
+    pub width: Option<String>,
+}
+
+pub const USAGE: &str = "usage: catscii [--once [--width <columns>]]";
+
+impl CliArgs {
+    pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut parsed = Self::default();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--once" => parsed.once = true,
+                "--width" => {
+                    parsed.width = Some(args.next().ok_or("--width needs a value")?);
+                }
+                _ => match arg.strip_prefix("--width=") {
+                    Some(width) => parsed.width = Some(width.to_owned()),
+                    None => return Err(format!("unknown argument {arg:?}")),
+                },
+            }
+        }
+
+        if parsed.width.is_some() && !parsed.once {
+            return Err("--width only makes sense with --once".to_owned());
+        }
+        Ok(parsed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<CliArgs, String> {
+        CliArgs::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn serves_without_arguments() {
+        assert_eq!(parse(&[]), Ok(CliArgs::default()));
+    }
+
+    #[test]
+    fn reads_once_and_width() {
+        for args in [
+            &["--once", "--width", "120"][..],
+            &["--width=120", "--once"],
+        ] {
+            let expected = CliArgs {
+                once: true,
+                width: Some("120".to_owned()),
+            };
+            assert_eq!(parse(args), Ok(expected));
+        }
+    }
+
+    #[test]
+    fn rejects_bad_arguments() {
+        assert!(parse(&["--width", "120"]).is_err());
+        assert!(parse(&["--once", "--width"]).is_err());
+        assert!(parse(&["--twice"]).is_err());
+    }
+}
@@ -0,0 +1,109 @@
+use axum::http::HeaderMap;
+use std::net::{IpAddr, SocketAddr};
+
+/// The address of whoever made the request. Each of the `trusted_hops`
+/// proxies in front of us appends the address it got the request from to
+/// `X-Forwarded-For`, so the client is that many entries from the right:
+/// anything further left came from the client and could be made up. Without
+/// `X-Forwarded-For` we take the first proxy's `X-Real-IP`, and without
+/// trusted proxies, the socket peer.
+pub fn client_ip(headers: &HeaderMap, peer: SocketAddr, trusted_hops: usize) -> IpAddr {
+    if trusted_hops == 0 {
+        return peer.ip();
+    }
+
+    let forwarded: Vec<&str> = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|h| h.to_str().ok())
+        .flat_map(|h| h.split(','))
+        .map(str::trim)
+        .filter(|ip| !ip.is_empty())
+        .collect();
+    if !forwarded.is_empty() {
+        // Fewer entries than proxies means the request didn't come through
+        // all of them; the leftmost one is still the furthest we can trust.
+        let index = forwarded.len().saturating_sub(trusted_hops);
+        return forwarded[index].parse().unwrap_or_else(|_| peer.ip());
+    }
+
+    headers
+        .get("x-real-ip")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|ip| ip.trim().parse().ok())
+        .unwrap_or_else(|| peer.ip())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer() -> SocketAddr {
+        "10.0.0.1:4000".parse().unwrap()
+    }
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(*name, value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn ignores_headers_without_trusted_proxies() {
+        let headers = headers(&[("x-forwarded-for", "1.2.3.4"), ("x-real-ip", "5.6.7.8")]);
+        assert_eq!(client_ip(&headers, peer(), 0), peer().ip());
+    }
+
+    #[test]
+    fn single_hop_takes_the_rightmost_entry() {
+        // The client claimed to be 6.6.6.6, our proxy saw 1.2.3.4.
+        let headers = headers(&[("x-forwarded-for", "6.6.6.6, 1.2.3.4")]);
+        assert_eq!(
+            client_ip(&headers, peer(), 1),
+            "1.2.3.4".parse::<IpAddr>().unwrap()
+        );
+    }
+
+    #[test]
+    fn multi_hop_counts_from_the_right() {
+        let headers = headers(&[("x-forwarded-for", "6.6.6.6, 1.2.3.4, 172.16.0.2")]);
+        assert_eq!(
+            client_ip(&headers, peer(), 2),
+            "1.2.3.4".parse::<IpAddr>().unwrap()
+        );
+    }
+
+    #[test]
+    fn multi_hop_reads_repeated_headers_in_order() {
+        let headers = headers(&[
+            ("x-forwarded-for", "6.6.6.6, 1.2.3.4"),
+            ("x-forwarded-for", "172.16.0.2"),
+        ]);
+        assert_eq!(
+            client_ip(&headers, peer(), 2),
+            "1.2.3.4".parse::<IpAddr>().unwrap()
+        );
+    }
+
+    #[test]
+    fn short_chain_takes_the_leftmost_entry() {
+        let headers = headers(&[("x-forwarded-for", "1.2.3.4")]);
+        assert_eq!(
+            client_ip(&headers, peer(), 3),
+            "1.2.3.4".parse::<IpAddr>().unwrap()
+        );
+    }
+
+    #[test]
+    fn falls_back_to_x_real_ip_then_peer() {
+        let real_ip = headers(&[("x-real-ip", "1.2.3.4")]);
+        assert_eq!(
+            client_ip(&real_ip, peer(), 1),
+            "1.2.3.4".parse::<IpAddr>().unwrap()
+        );
+        let garbage = headers(&[("x-forwarded-for", "not an ip")]);
+        assert_eq!(client_ip(&garbage, peer(), 1), peer().ip());
+    }
+}
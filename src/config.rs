@@ -0,0 +1,691 @@
+use axum::http::HeaderValue;
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    num::{NonZeroU32, NonZeroU64, NonZeroUsize},
+    path::PathBuf,
+    str::FromStr,
+    time::Duration,
+};
+use tracing_subscriber::filter::Targets;
+
+use crate::retry::RetryPolicy;
+
+/// Everything we read from the environment, validated up front so that all
+/// misconfigurations are reported at once instead of one crash at a time.
+pub struct Config {
+    pub sentry_dsn: Option<String>,
+    pub honeycomb_api_key: Option<String>,
+    /// Fraction of requests traced, by request span name like `dog_get`.
+    pub trace_sample_rates: HashMap<String, f64>,
+    pub log_filter: Targets,
+    pub log_format: LogFormat,
+    pub listen_addr: SocketAddr,
+    pub art_cache_ttl: Duration,
+    pub art_cache_entries: usize,
+    /// How long clients and CDNs may reuse art we sent them.
+    pub art_max_age: Duration,
+    pub download_cache_entries: usize,
+    /// Total size of the images in the download cache, in bytes.
+    pub download_cache_bytes: usize,
+    pub upstream_timeout: Duration,
+    pub upstream_connect_timeout: Duration,
+    /// Retries and backoff for upstream requests.
+    pub upstream_retry: RetryPolicy,
+    /// Consecutive Cat API failures before we stop calling it for a while.
+    pub circuit_breaker_threshold: u32,
+    pub circuit_breaker_cooldown: Duration,
+    /// How long a whole request may take before we give up with a 504.
+    pub request_timeout: Duration,
+    /// Budget for fetching, downloading and converting, kept under
+    /// `request_timeout` so that timeouts get a clearer error.
+    pub pipeline_timeout: Duration,
+    /// Threads running async tasks.
+    pub tokio_workers: NonZeroUsize,
+    /// Most threads decoding and converting images at once.
+    pub tokio_blocking_threads: NonZeroUsize,
+    /// Art requests served at once, past which clients are told to come back.
+    pub max_in_flight: usize,
+    /// Marked sensitive so it never shows up in `Debug` output.
+    pub cat_api_key: Option<HeaderValue>,
+    pub verbose_errors: bool,
+    pub cors_allowed_origins: Option<Vec<HeaderValue>>,
+    pub rate_limit_per_second: f64,
+    pub rate_limit_burst: u32,
+    /// Proxies in front of us that append to `X-Forwarded-For`, whose word
+    /// we take about who the client is. None means we trust no headers.
+    pub trusted_proxy_hops: usize,
+    /// Density ramp used when requests don't pass `?chars=`.
+    pub ascii_characters: Option<String>,
+    /// Vertical squash applied before conversion when requests don't pass
+    /// `?aspect=`, in percent.
+    pub aspect_ratio: u32,
+    /// Hosts `/convert` may download from. Any host is allowed when unset.
+    pub convert_allowed_hosts: Option<Vec<String>>,
+    pub max_download_bytes: usize,
+    /// Longest side images are shrunk to before conversion, in pixels.
+    pub max_image_dimension: u32,
+    /// Longest art we send, in characters. Bigger art is drawn narrower.
+    pub max_output_chars: usize,
+    /// Time between cats on `/stream`.
+    pub stream_interval: Duration,
+    /// Where to keep a copy of every converted Cat API image and its art, if
+    /// at all. Created if it doesn't exist.
+    pub archive_dir: Option<PathBuf>,
+    /// Fetch and convert a cat at startup, so the first request isn't the
+    /// one paying for cold connections and caches.
+    pub prefetch_on_start: bool,
+    /// Serve a bundled cat instead of an error when upstream fails.
+    pub fallback_enabled: bool,
+    /// Where cats come from, for running behind a caching proxy.
+    pub cat_api_url: reqwest::Url,
+    /// Let the Cat API fall back to plain HTTP when HTTPS won't connect. The
+    /// API key is left out of those requests.
+    pub cat_api_allow_http: bool,
+    /// Where `/dog` gets its dogs.
+    pub dog_api_url: reqwest::Url,
+    /// Speak HTTP/2 only, with prior knowledge, for proxies that multiplex
+    /// over one connection. `/ws` needs HTTP/1.1 and stops working.
+    pub http2_only: bool,
+    /// Serve HTTPS instead of HTTP when set.
+    pub tls: Option<TlsPaths>,
+    /// Required for admin routes like `/metrics` when set.
+    pub basic_auth: Option<Credentials>,
+}
+
+/// How log lines are written out.
+#[derive(Clone, Copy)]
+pub enum LogFormat {
+    /// One JSON object per line, for log collectors.
+    Json,
+    /// Multi-line and colored, for reading in a terminal.
+    Pretty,
+}
+
+impl FromStr for LogFormat {
+    type Err = ();
+
+    fn from_str(raw: &str) -> Result<Self, ()> {
+        match raw {
+            "json" => Ok(Self::Json),
+            "pretty" => Ok(Self::Pretty),
+            _ => Err(()),
+        }
+    }
+}
+
+pub struct Credentials {
+    pub user: String,
+    pub password: String,
+}
+
+/// PEM files to terminate TLS with.
+pub struct TlsPaths {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+impl Config {
+    pub fn from_env() -> color_eyre::Result<Self> {
+        Self::from_vars(|name| std::env::var(name).ok())
+    }
+
+    /// Like [`Config::from_env`], reading variables through `var` instead.
+    pub fn from_vars(var: impl Fn(&str) -> Option<String>) -> color_eyre::Result<Self> {
+        let mut vars = Vars {
+            var,
+            problems: Vec::new(),
+        };
+
+        let config = Self {
+            sentry_dsn: vars.optional("SENTRY_DSN"),
+            honeycomb_api_key: vars.optional("HONEYCOMB_API_KEY"),
+            trace_sample_rates: vars.sample_rates("TRACE_SAMPLE_RATES"),
+            log_filter: vars.parsed_or("RUST_LOG", "a valid tracing filter", || {
+                Targets::default().with_default(tracing::Level::INFO)
+            }),
+            log_format: vars.parsed_or("LOG_FORMAT", "json or pretty", || LogFormat::Json),
+            listen_addr: vars.parsed_or(
+                "LISTEN_ADDR",
+                "a socket address like 0.0.0.0:8080",
+                || SocketAddr::from(([0, 0, 0, 0], 8080)),
+            ),
+            art_cache_ttl: vars.secs("ART_CACHE_TTL_SECS", 60),
+            art_max_age: vars.secs("ART_MAX_AGE_SECS", 10),
+            art_cache_entries: vars.parsed_or("ART_CACHE_ENTRIES", "a number of entries", || 256),
+            download_cache_entries: vars.parsed_or(
+                "DOWNLOAD_CACHE_ENTRIES",
+                "a number of entries",
+                || 32,
+            ),
+            download_cache_bytes: vars.parsed_or(
+                "DOWNLOAD_CACHE_BYTES",
+                "a number of bytes",
+                || 64 * 1024 * 1024,
+            ),
+            upstream_timeout: vars.secs("UPSTREAM_TIMEOUT_SECS", 10),
+            upstream_connect_timeout: vars.secs("UPSTREAM_CONNECT_TIMEOUT_SECS", 3),
+            upstream_retry: vars.retry_policy(),
+            request_timeout: vars.secs("REQUEST_TIMEOUT_SECS", 30),
+            pipeline_timeout: vars.secs("PIPELINE_TIMEOUT_SECS", 20),
+            tokio_workers: vars.parsed_or("TOKIO_WORKERS", "a positive number of threads", || {
+                std::thread::available_parallelism().unwrap_or(NonZeroUsize::MIN)
+            }),
+            tokio_blocking_threads: vars.parsed_or(
+                "TOKIO_BLOCKING_THREADS",
+                "a positive number of threads",
+                // tokio's own default.
+                || NonZeroUsize::new(512).unwrap(),
+            ),
+            max_in_flight: vars
+                .parsed_or("MAX_IN_FLIGHT", "a positive number of requests", || {
+                    NonZeroUsize::new(20).unwrap()
+                })
+                .get(),
+            circuit_breaker_threshold: vars.parsed_or(
+                "CIRCUIT_BREAKER_THRESHOLD",
+                "a number of failures",
+                || 5,
+            ),
+            circuit_breaker_cooldown: vars.secs("CIRCUIT_BREAKER_COOLDOWN_SECS", 30),
+            cat_api_key: vars.header_value("CAT_API_KEY").map(|mut key| {
+                key.set_sensitive(true);
+                key
+            }),
+            verbose_errors: vars.flag("VERBOSE_ERRORS"),
+            cors_allowed_origins: vars.origins("CORS_ALLOWED_ORIGINS"),
+            rate_limit_per_second: vars.per_second("RATE_LIMIT_PER_SECOND", 1.0),
+            rate_limit_burst: vars
+                .parsed_or("RATE_LIMIT_BURST", "a positive number of requests", || {
+                    NonZeroU32::new(10).unwrap()
+                })
+                .get(),
+            trusted_proxy_hops: {
+                // The flag predates multiple hops and means a single proxy.
+                let trust_proxy = vars.flag("TRUST_PROXY");
+                vars.parsed_or("TRUSTED_PROXY_HOPS", "a number of proxies", || {
+                    usize::from(trust_proxy)
+                })
+            },
+            ascii_characters: vars.characters("ASCII_CHARACTERS"),
+            aspect_ratio: vars.aspect_ratio("ASPECT_RATIO"),
+            convert_allowed_hosts: vars.optional("CONVERT_ALLOWED_HOSTS").map(|hosts| {
+                hosts
+                    .split(',')
+                    .map(|host| host.trim().to_owned())
+                    .filter(|host| !host.is_empty())
+                    .collect()
+            }),
+            max_download_bytes: vars.parsed_or("MAX_DOWNLOAD_BYTES", "a number of bytes", || {
+                10 * 1024 * 1024
+            }),
+            max_image_dimension: vars.parsed_or(
+                "MAX_IMAGE_DIMENSION",
+                "a number of pixels",
+                || 1000,
+            ),
+            max_output_chars: vars
+                .parsed_or("MAX_OUTPUT_CHARS", "a number of characters", || 2_000_000),
+            // Zero would send cats as fast as we can fetch them.
+            stream_interval: Duration::from_secs(
+                vars.parsed_or(
+                    "STREAM_INTERVAL_SECS",
+                    "a positive number of seconds",
+                    || NonZeroU64::new(5).unwrap(),
+                )
+                .get(),
+            ),
+            archive_dir: vars.dir("ARCHIVE_DIR"),
+            prefetch_on_start: vars.flag("PREFETCH_ON_START"),
+            fallback_enabled: vars.flag("FALLBACK_ENABLED"),
+            cat_api_url: vars.http_url("CAT_API_BASE_URL", crate::CAT_API_URL),
+            cat_api_allow_http: vars.flag("CAT_API_ALLOW_HTTP"),
+            dog_api_url: vars.http_url("DOG_API_URL", crate::source::DOG_API_URL),
+            http2_only: vars.flag("HTTP2_ONLY"),
+            tls: vars
+                .pair("TLS_CERT_PATH", "TLS_KEY_PATH")
+                .map(|(cert, key)| TlsPaths {
+                    cert: cert.into(),
+                    key: key.into(),
+                }),
+            basic_auth: vars
+                .pair("BASIC_AUTH_USER", "BASIC_AUTH_PASS")
+                .map(|(user, password)| Credentials { user, password }),
+        };
+
+        // Otherwise the request times out first and the pipeline's clearer
+        // error never gets sent.
+        if config.pipeline_timeout >= config.request_timeout {
+            vars.problems.push(
+                "$PIPELINE_TIMEOUT_SECS should be less than $REQUEST_TIMEOUT_SECS".to_owned(),
+            );
+        }
+
+        if vars.problems.is_empty() {
+            Ok(config)
+        } else {
+            Err(color_eyre::eyre::eyre!(
+                "invalid configuration:\n  - {}",
+                vars.problems.join("\n  - ")
+            ))
+        }
+    }
+}
+
+/// Reads variables, collecting every problem instead of stopping at the
+/// first one. Invalid values are replaced with defaults so reading can go on.
+struct Vars<F> {
+    var: F,
+    problems: Vec<String>,
+}
+
+impl<F: Fn(&str) -> Option<String>> Vars<F> {
+    fn optional(&self, name: &str) -> Option<String> {
+        (self.var)(name).filter(|value| !value.is_empty())
+    }
+
+    fn parsed_or<T: FromStr>(
+        &mut self,
+        name: &str,
+        expected: &str,
+        default: impl FnOnce() -> T,
+    ) -> T {
+        match self.optional(name).map(|raw| raw.parse::<T>()) {
+            Some(Ok(value)) => value,
+            Some(Err(_)) => {
+                self.problems.push(format!("${name} should be {expected}"));
+                default()
+            }
+            None => default(),
+        }
+    }
+
+    fn secs(&mut self, name: &str, default: u64) -> Duration {
+        Duration::from_secs(self.parsed_or(name, "a number of seconds", || default))
+    }
+
+    fn millis(&mut self, name: &str, default: Duration) -> Duration {
+        let default = default.as_millis() as u64;
+        Duration::from_millis(self.parsed_or(name, "a number of milliseconds", || default))
+    }
+
+    fn flag(&mut self, name: &str) -> bool {
+        self.flag_or(name, false)
+    }
+
+    fn flag_or(&mut self, name: &str, default: bool) -> bool {
+        match self.optional(name).as_deref() {
+            Some("true" | "1") => true,
+            Some("false" | "0") => false,
+            None => default,
+            Some(_) => {
+                self.problems
+                    .push(format!("${name} should be true or false"));
+                default
+            }
+        }
+    }
+
+    fn retry_policy(&mut self) -> RetryPolicy {
+        let defaults = RetryPolicy::default();
+        let policy = RetryPolicy {
+            max_retries: self.parsed_or("UPSTREAM_MAX_RETRIES", "a non-negative integer", || {
+                defaults.max_retries
+            }),
+            base_delay: self.millis("UPSTREAM_RETRY_BASE_DELAY_MS", defaults.base_delay),
+            multiplier: self.parsed_or("UPSTREAM_RETRY_MULTIPLIER", "a number", || {
+                defaults.multiplier
+            }),
+            max_delay: self.millis("UPSTREAM_RETRY_MAX_DELAY_MS", defaults.max_delay),
+            jitter: self.flag_or("UPSTREAM_RETRY_JITTER", defaults.jitter),
+        };
+
+        if !(policy.multiplier.is_finite() && policy.multiplier >= 1.0) {
+            self.problems
+                .push("$UPSTREAM_RETRY_MULTIPLIER should be at least 1".to_owned());
+        }
+        if policy.base_delay > policy.max_delay {
+            self.problems.push(
+                "$UPSTREAM_RETRY_BASE_DELAY_MS should not be more than $UPSTREAM_RETRY_MAX_DELAY_MS"
+                    .to_owned(),
+            );
+        }
+        policy
+    }
+
+    /// A rate, which has to be positive for things to ever happen at all.
+    fn per_second(&mut self, name: &str, default: f64) -> f64 {
+        let rate = self.parsed_or(name, "a number", || default);
+        if rate.is_finite() && rate > 0.0 {
+            rate
+        } else {
+            self.problems
+                .push(format!("${name} should be a number above 0"));
+            default
+        }
+    }
+
+    fn characters(&mut self, name: &str) -> Option<String> {
+        // Not `optional`, since a lone space is a perfectly good ramp.
+        let raw = (self.var)(name)?;
+        match crate::validate_characters(&raw) {
+            Ok(_) => Some(raw),
+            Err(message) => {
+                self.problems.push(format!("${name}: {message}"));
+                None
+            }
+        }
+    }
+
+    /// Two variables that only make sense together.
+    fn pair(&mut self, first: &str, second: &str) -> Option<(String, String)> {
+        match (self.optional(first), self.optional(second)) {
+            (Some(a), Some(b)) => Some((a, b)),
+            (None, None) => None,
+            _ => {
+                self.problems
+                    .push(format!("${first} and ${second} must be set together"));
+                None
+            }
+        }
+    }
+
+    /// A directory we write to, created now so a bad path shows up here
+    /// rather than as a warning per write.
+    fn dir(&mut self, name: &str) -> Option<PathBuf> {
+        let dir = PathBuf::from(self.optional(name)?);
+        match std::fs::create_dir_all(&dir) {
+            Ok(()) => Some(dir),
+            Err(e) => {
+                self.problems.push(format!("${name} can't be created: {e}"));
+                None
+            }
+        }
+    }
+
+    fn http_url(&mut self, name: &str, default: &str) -> reqwest::Url {
+        let default = || reqwest::Url::parse(default).expect("default URL should be valid");
+        let url = self.parsed_or(name, "an http(s) URL", default);
+        if matches!(url.scheme(), "http" | "https") {
+            url
+        } else {
+            self.problems
+                .push(format!("${name} should be an http(s) URL"));
+            default()
+        }
+    }
+
+    fn aspect_ratio(&mut self, name: &str) -> u32 {
+        let Some(raw) = self.optional(name) else {
+            return crate::DEFAULT_ASPECT_RATIO;
+        };
+        crate::parse_aspect_ratio(&raw).unwrap_or_else(|message| {
+            self.problems.push(format!("${name}: {message}"));
+            crate::DEFAULT_ASPECT_RATIO
+        })
+    }
+
+    /// Comma-separated `name=rate` pairs, rates between 0 and 1.
+    fn sample_rates(&mut self, name: &str) -> HashMap<String, f64> {
+        let Some(raw) = self.optional(name) else {
+            return HashMap::new();
+        };
+        let rates: Option<HashMap<_, _>> = raw
+            .split(',')
+            .map(|pair| {
+                let (route, rate) = pair.split_once('=')?;
+                let rate = rate.trim().parse::<f64>().ok()?;
+                (0.0..=1.0)
+                    .contains(&rate)
+                    .then(|| (route.trim().to_owned(), rate))
+            })
+            .collect();
+        rates.unwrap_or_else(|| {
+            self.problems.push(format!(
+                "${name} should be a comma-separated list of name=rate, like dog_get=0.1"
+            ));
+            HashMap::new()
+        })
+    }
+
+    fn header_value(&mut self, name: &str) -> Option<HeaderValue> {
+        let raw = self.optional(name)?;
+        match HeaderValue::from_str(&raw) {
+            Ok(value) => Some(value),
+            Err(_) => {
+                self.problems
+                    .push(format!("${name} should be a valid header value"));
+                None
+            }
+        }
+    }
+
+    fn origins(&mut self, name: &str) -> Option<Vec<HeaderValue>> {
+        let raw = self.optional(name)?;
+        let origins = raw
+            .split(',')
+            .map(str::trim)
+            .filter(|origin| !origin.is_empty());
+        // tower-http refuses a wildcard in a list, and panics about it.
+        if origins.clone().any(|origin| origin == "*") {
+            self.problems
+                .push(format!("${name} should list origins, * isn't supported"));
+            return None;
+        }
+        let origins: Result<Vec<_>, _> = origins.map(HeaderValue::from_str).collect();
+        match origins {
+            Ok(origins) => Some(origins),
+            Err(_) => {
+                self.problems.push(format!(
+                    "${name} should be a comma-separated list of origins"
+                ));
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from_vars(vars: &[(&str, &str)]) -> color_eyre::Result<Config> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        Config::from_vars(|name| vars.get(name).cloned())
+    }
+
+    fn problems(vars: &[(&str, &str)]) -> String {
+        from_vars(vars)
+            .err()
+            .expect("config should be invalid")
+            .to_string()
+    }
+
+    #[test]
+    fn defaults_without_any_variables() {
+        let config = from_vars(&[]).unwrap();
+        assert_eq!(config.listen_addr, SocketAddr::from(([0, 0, 0, 0], 8080)));
+        assert_eq!(config.art_cache_ttl, Duration::from_secs(60));
+        assert_eq!(config.cat_api_url.as_str(), crate::CAT_API_URL);
+        assert_eq!(config.dog_api_url.as_str(), crate::source::DOG_API_URL);
+        assert!(config.sentry_dsn.is_none());
+        assert!(config.basic_auth.is_none());
+        assert!(config.convert_allowed_hosts.is_none());
+    }
+
+    #[test]
+    fn empty_variables_count_as_unset() {
+        let config = from_vars(&[("SENTRY_DSN", ""), ("LISTEN_ADDR", "")]).unwrap();
+        assert!(config.sentry_dsn.is_none());
+        assert_eq!(config.listen_addr, SocketAddr::from(([0, 0, 0, 0], 8080)));
+    }
+
+    #[test]
+    fn reads_variables() {
+        let config = from_vars(&[
+            ("LISTEN_ADDR", "127.0.0.1:3000"),
+            ("ART_CACHE_TTL_SECS", "5"),
+            ("VERBOSE_ERRORS", "1"),
+            ("CONVERT_ALLOWED_HOSTS", "example.com, , cats.test"),
+            ("BASIC_AUTH_USER", "admin"),
+            ("BASIC_AUTH_PASS", "hunter2"),
+        ])
+        .unwrap();
+        assert_eq!(config.listen_addr, SocketAddr::from(([127, 0, 0, 1], 3000)));
+        assert_eq!(config.art_cache_ttl, Duration::from_secs(5));
+        assert!(config.verbose_errors);
+        assert_eq!(
+            config.convert_allowed_hosts,
+            Some(vec!["example.com".to_owned(), "cats.test".to_owned()])
+        );
+        assert_eq!(config.basic_auth.unwrap().user, "admin");
+    }
+
+    #[test]
+    fn validates_the_retry_policy() {
+        let config = from_vars(&[
+            ("UPSTREAM_MAX_RETRIES", "1"),
+            ("UPSTREAM_RETRY_BASE_DELAY_MS", "50"),
+            ("UPSTREAM_RETRY_JITTER", "false"),
+        ])
+        .unwrap();
+        assert_eq!(config.upstream_retry.max_retries, 1);
+        assert_eq!(config.upstream_retry.base_delay, Duration::from_millis(50));
+        assert!(!config.upstream_retry.jitter);
+
+        let reported = problems(&[
+            ("UPSTREAM_RETRY_MULTIPLIER", "0.5"),
+            ("UPSTREAM_RETRY_BASE_DELAY_MS", "10000"),
+            ("UPSTREAM_RETRY_MAX_DELAY_MS", "100"),
+        ]);
+        assert!(
+            reported.contains("$UPSTREAM_RETRY_MULTIPLIER"),
+            "{reported}"
+        );
+        assert!(
+            reported.contains("$UPSTREAM_RETRY_BASE_DELAY_MS"),
+            "{reported}"
+        );
+        let reported = problems(&[("UPSTREAM_RETRY_MULTIPLIER", "NaN")]);
+        assert!(
+            reported.contains("$UPSTREAM_RETRY_MULTIPLIER"),
+            "{reported}"
+        );
+    }
+
+    #[test]
+    fn rate_limits_must_be_positive() {
+        let config = from_vars(&[("RATE_LIMIT_PER_SECOND", "0.5")]).unwrap();
+        assert_eq!(config.rate_limit_per_second, 0.5);
+        for rate in ["0", "-1", "inf", "NaN"] {
+            let reported = problems(&[("RATE_LIMIT_PER_SECOND", rate)]);
+            assert!(reported.contains("$RATE_LIMIT_PER_SECOND"), "{rate}");
+        }
+        let reported = problems(&[("RATE_LIMIT_BURST", "0")]);
+        assert!(reported.contains("$RATE_LIMIT_BURST"), "{reported}");
+    }
+
+    #[test]
+    fn in_flight_requests_must_be_allowed() {
+        assert_eq!(from_vars(&[]).unwrap().max_in_flight, 20);
+        let reported = problems(&[("MAX_IN_FLIGHT", "0")]);
+        assert!(reported.contains("$MAX_IN_FLIGHT"), "{reported}");
+    }
+
+    #[test]
+    fn streams_need_an_interval() {
+        let config = from_vars(&[("STREAM_INTERVAL_SECS", "2")]).unwrap();
+        assert_eq!(config.stream_interval, Duration::from_secs(2));
+        let reported = problems(&[("STREAM_INTERVAL_SECS", "0")]);
+        assert!(reported.contains("$STREAM_INTERVAL_SECS"), "{reported}");
+    }
+
+    #[test]
+    fn pipeline_times_out_before_the_request() {
+        let config = from_vars(&[
+            ("REQUEST_TIMEOUT_SECS", "5"),
+            ("PIPELINE_TIMEOUT_SECS", "4"),
+        ])
+        .unwrap();
+        assert_eq!(config.pipeline_timeout, Duration::from_secs(4));
+        for pipeline in ["5", "6"] {
+            let reported = problems(&[
+                ("REQUEST_TIMEOUT_SECS", "5"),
+                ("PIPELINE_TIMEOUT_SECS", pipeline),
+            ]);
+            assert!(reported.contains("$PIPELINE_TIMEOUT_SECS"), "{reported}");
+        }
+        let reported = problems(&[("REQUEST_TIMEOUT_SECS", "10")]);
+        assert!(reported.contains("$PIPELINE_TIMEOUT_SECS"), "{reported}");
+    }
+
+    #[test]
+    fn rejects_an_empty_character_ramp() {
+        let config = from_vars(&[("ASCII_CHARACTERS", " .#")]).unwrap();
+        assert_eq!(config.ascii_characters.as_deref(), Some(" .#"));
+        let reported = problems(&[("ASCII_CHARACTERS", "")]);
+        assert!(
+            reported.contains("$ASCII_CHARACTERS: chars must be between 1 and 64"),
+            "{reported}"
+        );
+    }
+
+    #[test]
+    fn reads_cors_origins() {
+        let config =
+            from_vars(&[("CORS_ALLOWED_ORIGINS", "https://a.test, ,https://b.test,")]).unwrap();
+        assert_eq!(
+            config.cors_allowed_origins.unwrap(),
+            ["https://a.test", "https://b.test"]
+        );
+        let reported = problems(&[("CORS_ALLOWED_ORIGINS", "https://a.test, *")]);
+        assert!(reported.contains("$CORS_ALLOWED_ORIGINS"), "{reported}");
+    }
+
+    #[test]
+    fn creates_the_archive_dir() {
+        let dir = std::env::temp_dir().join(format!("catscii-archive-{}", std::process::id()));
+        let nested = dir.join("cats");
+        let config = from_vars(&[("ARCHIVE_DIR", nested.to_str().unwrap())]).unwrap();
+        assert_eq!(config.archive_dir.as_deref(), Some(nested.as_path()));
+        assert!(nested.is_dir());
+
+        // A file where a parent directory should be.
+        let file = dir.join("file");
+        std::fs::write(&file, "").unwrap();
+        let reported = problems(&[("ARCHIVE_DIR", file.join("cats").to_str().unwrap())]);
+        assert!(reported.contains("$ARCHIVE_DIR"), "{reported}");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn trusts_proxies_by_count_or_legacy_flag() {
+        assert_eq!(from_vars(&[]).unwrap().trusted_proxy_hops, 0);
+        assert_eq!(
+            from_vars(&[("TRUST_PROXY", "true")])
+                .unwrap()
+                .trusted_proxy_hops,
+            1
+        );
+        let config = from_vars(&[("TRUST_PROXY", "true"), ("TRUSTED_PROXY_HOPS", "2")]).unwrap();
+        assert_eq!(config.trusted_proxy_hops, 2);
+    }
+
+    #[test]
+    fn reports_every_problem_at_once() {
+        let problems = problems(&[
+            ("LISTEN_ADDR", "nowhere"),
+            ("VERBOSE_ERRORS", "maybe"),
+            ("BASIC_AUTH_USER", "admin"),
+        ]);
+        assert!(problems.contains("$LISTEN_ADDR"), "{problems}");
+        assert!(problems.contains("$VERBOSE_ERRORS"), "{problems}");
+        assert!(
+            problems.contains("$BASIC_AUTH_USER and $BASIC_AUTH_PASS"),
+            "{problems}"
+        );
+    }
+}
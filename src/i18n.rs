@@ -0,0 +1,169 @@
+use axum::http::{header, HeaderMap};
+
+/// Languages error messages are available in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Language {
+    English,
+    Spanish,
+    French,
+}
+
+impl Language {
+    /// The best match for `Accept-Language`, English when nothing matches.
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let accept = headers
+            .get(header::ACCEPT_LANGUAGE)
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or_default();
+
+        let mut best = (Self::English, 0.0);
+        for entry in accept.split(',') {
+            let mut parts = entry.split(';').map(str::trim);
+            let tag = parts.next().unwrap_or_default();
+            let q = parts
+                .find_map(|param| param.strip_prefix("q="))
+                .and_then(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            let primary = tag.split('-').next().unwrap_or_default();
+            let language = match primary.to_ascii_lowercase().as_str() {
+                "en" => Self::English,
+                "es" => Self::Spanish,
+                "fr" => Self::French,
+                _ => continue,
+            };
+            if q > best.1 {
+                best = (language, q);
+            }
+        }
+        best.0
+    }
+}
+
+/// The fixed messages in our error bodies and their translations, as
+/// (English, Spanish, French).
+const MESSAGES: &[(&str, &str, &str)] = &[
+    (
+        "Something went wrong",
+        "Algo salió mal",
+        "Une erreur s'est produite",
+    ),
+    (
+        "Nothing here, try GET / for a cat",
+        "Aquí no hay nada, prueba GET / para ver un gato",
+        "Rien ici, essayez GET / pour un chat",
+    ),
+    (
+        "Method not allowed",
+        "Método no permitido",
+        "Méthode non autorisée",
+    ),
+    ("Unauthorized", "No autorizado", "Non autorisé"),
+    (
+        "Too many requests, slow down",
+        "Demasiadas solicitudes, ve más despacio",
+        "Trop de requêtes, ralentissez",
+    ),
+    (
+        "Request timed out",
+        "La solicitud tardó demasiado",
+        "La requête a expiré",
+    ),
+    (
+        "Too busy right now, try again shortly",
+        "Demasiado ocupado ahora mismo, inténtalo de nuevo en breve",
+        "Trop occupé pour le moment, réessayez bientôt",
+    ),
+    (
+        "Upstream timed out",
+        "El servidor de origen tardó demasiado",
+        "Le serveur d'origine a expiré",
+    ),
+    (
+        "Upstream request failed",
+        "Falló la solicitud al servidor de origen",
+        "La requête au serveur d'origine a échoué",
+    ),
+    (
+        "The Cat API is unavailable, try again later",
+        "The Cat API no está disponible, inténtalo más tarde",
+        "The Cat API est indisponible, réessayez plus tard",
+    ),
+    (
+        "The Cat API didn't send any cats, try again",
+        "The Cat API no envió ningún gato, inténtalo de nuevo",
+        "The Cat API n'a envoyé aucun chat, réessayez",
+    ),
+    (
+        "Image format not recognized",
+        "Formato de imagen no reconocido",
+        "Format d'image non reconnu",
+    ),
+    (
+        "Image download was empty",
+        "La imagen descargada estaba vacía",
+        "L'image téléchargée était vide",
+    ),
+];
+
+/// `message` in `language`, or as is when we have no translation for it.
+pub fn translate(message: &str, language: Language) -> &str {
+    let Some(&(english, spanish, french)) =
+        MESSAGES.iter().find(|(english, ..)| *english == message)
+    else {
+        return message;
+    };
+    match language {
+        Language::English => english,
+        Language::Spanish => spanish,
+        Language::French => french,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn language(accept_language: &str) -> Language {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT_LANGUAGE, accept_language.parse().unwrap());
+        Language::from_headers(&headers)
+    }
+
+    #[test]
+    fn picks_the_preferred_language_we_have() {
+        assert_eq!(language("es-MX"), Language::Spanish);
+        assert_eq!(language("de, fr;q=0.8, en;q=0.5"), Language::French);
+        assert_eq!(language("en;q=0.4, es;q=0.9"), Language::Spanish);
+        assert_eq!(language("fr;q=nonsense"), Language::French);
+    }
+
+    #[test]
+    fn defaults_to_english() {
+        assert_eq!(Language::from_headers(&HeaderMap::new()), Language::English);
+        assert_eq!(language("de, ja"), Language::English);
+    }
+
+    #[test]
+    fn translates_known_messages_only() {
+        assert_eq!(
+            translate("Request timed out", Language::French),
+            "La requête a expiré"
+        );
+        assert_eq!(
+            translate("Request timed out", Language::English),
+            "Request timed out"
+        );
+        assert_eq!(
+            translate("width must be a number", Language::Spanish),
+            "width must be a number"
+        );
+    }
+
+    #[test]
+    fn every_message_is_translated() {
+        for (english, spanish, french) in MESSAGES {
+            assert!(!spanish.is_empty() && spanish != english, "{english}");
+            assert!(!french.is_empty() && french != english, "{english}");
+        }
+    }
+}
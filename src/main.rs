@@ -1,79 +1,1209 @@
 use axum::{
     body::BoxBody,
-    extract::State,
-    http::{header, HeaderMap},
-    response::{IntoResponse, Response},
-    routing::get,
-    Router,
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Path, Query, State,
+    },
+    http::{header, HeaderMap, HeaderName, HeaderValue, Method, Request},
+    middleware::{self, Next},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+    routing::{get, put},
+    Json, Router,
 };
+use axum_server::tls_rustls::RustlsConfig;
+use color_eyre::eyre::WrapErr;
+use futures_util::{FutureExt as _, StreamExt};
+use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
 use opentelemetry::{
-    global,
-    trace::{get_active_span, FutureExt, Span, Status, TraceContextExt, Tracer},
+    global::{self, BoxedSpan},
+    propagation::Extractor,
+    sdk::propagation::TraceContextPropagator,
+    trace::{get_active_span, FutureExt, Span, Status, TraceContextExt, TraceId, Tracer},
     Context, KeyValue,
 };
 use reqwest::StatusCode;
-use serde::Deserialize;
-use std::str::FromStr;
-use tracing::{info, warn, Level};
-use tracing_subscriber::{filter::Targets, layer::SubscriberExt, util::SubscriberInitExt};
+use sentry::SentryFutureExt;
+use serde::{Deserialize, Serialize};
+use std::{
+    borrow::Cow,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tower_http::{
+    compression::CompressionLayer,
+    cors::{self, AllowOrigin, CorsLayer},
+};
+use tracing::{error, info, warn};
+use tracing_subscriber::{
+    filter::Targets,
+    layer::{Layer, SubscriberExt},
+    reload,
+    util::SubscriberInitExt,
+};
 
-#[derive(Clone)]
+mod access_log;
+mod archive;
+mod auth;
+mod braille;
+mod cache;
+mod circuit;
+mod cli;
+mod client_ip;
+mod config;
+mod i18n;
+#[cfg(feature = "openapi")]
+mod openapi;
+mod page;
+mod preset;
+mod rate_limit;
+mod retry;
+mod sampling;
+mod source;
+mod stats;
+
+use auth::{require_basic_auth, BasicAuth};
+use cache::{BoundedCache, TtlCache};
+use circuit::CircuitBreaker;
+use cli::{CliArgs, USAGE};
+use config::{Config, LogFormat, TlsPaths};
+use rate_limit::{rate_limit, RateLimiter};
+use retry::{send_with_retries, RetryPolicy};
+use source::{
+    AnimalSource, CatApi, CatByIdSource, CatImageProvider, CatSource, DogSource, UrlSource,
+};
+use stats::Stats;
+
+/// Built once in `main` and shared by every request through an `Arc`, so the
+/// HTTP client's connection pool and the caches are reused.
 struct ServerState {
     client: reqwest::Client,
+    /// Downloads for `/convert`, checking every redirect against
+    /// `convert_allowed_hosts` so that an allowed host can't bounce us
+    /// anywhere else.
+    convert_client: reqwest::Client,
+    /// Converted art, keyed by source image URL and render options.
+    art_cache: TtlCache<(String, ArtOptions), Art>,
+    /// Raw downloaded images, keyed by URL.
+    download_cache: BoundedCache<String, Vec<u8>>,
+    retry: RetryPolicy,
+    metrics: PrometheusHandle,
+    /// Sent as `x-api-key` to the Cat API when set. Marked sensitive so it
+    /// never shows up in `Debug` output.
+    cat_api_key: Option<HeaderValue>,
+    /// Send full error chains to clients instead of generic messages.
+    verbose_errors: bool,
+    /// What requests that don't ask for anything in particular get.
+    default_art_options: ArtOptions,
+    /// Hosts `/convert` may download from, or `None` for any.
+    convert_allowed_hosts: Option<Vec<String>>,
+    /// Largest image `download_file` accepts.
+    max_download_bytes: usize,
+    /// Longest side images are shrunk to before conversion.
+    max_image_dimension: u32,
+    /// Longest art we send, in characters.
+    max_output_chars: usize,
+    /// `Cache-Control` for successful art responses.
+    art_cache_control: HeaderValue,
+    /// Budget for making art, start to finish.
+    pipeline_timeout: Duration,
+    /// Cat API search endpoint, swappable so tests can point it at a mock.
+    cat_api_url: reqwest::Url,
+    /// Random dog endpoint, likewise.
+    dog_api_url: reqwest::Url,
+    /// Caps how many art requests hit upstream at once.
+    art_permits: Arc<Semaphore>,
+    /// Where cats come from, [`CatApi`] outside of tests.
+    cat_images: Box<dyn CatImageProvider>,
+    cat_api_breaker: CircuitBreaker,
+    /// Retry over plain HTTP when HTTPS can't connect, without the API key
+    /// so that it never crosses the network in cleartext.
+    cat_api_allow_http: bool,
+    /// Set when the Cat API told us we're out of requests, to when it said
+    /// we'd get more.
+    cat_api_quota_reset: Mutex<Option<Instant>>,
+    /// When the Cat API last answered successfully, for `/health?verbose=true`.
+    cat_api_last_success: Mutex<Option<SystemTime>>,
+    /// Whether traces go anywhere.
+    honeycomb_enabled: bool,
+    /// Serve [`FALLBACK_CAT`] when upstream fails.
+    fallback_enabled: bool,
+    /// Time between cats on `/stream`.
+    stream_interval: Duration,
+    /// Where to keep a copy of every conversion, if at all.
+    archive_dir: Option<PathBuf>,
+    stats: Stats,
+}
+
+impl ServerState {
+    fn new(config: &Config) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(config.upstream_timeout)
+            .connect_timeout(config.upstream_connect_timeout)
+            .build()
+            .expect("should be able to build the HTTP client");
+        let allowed_hosts = config.convert_allowed_hosts.clone();
+        let convert_client = reqwest::Client::builder()
+            .timeout(config.upstream_timeout)
+            .connect_timeout(config.upstream_connect_timeout)
+            .redirect(reqwest::redirect::Policy::custom(move |attempt| {
+                if attempt.previous().len() >= MAX_REDIRECTS {
+                    return attempt.error("too many redirects");
+                }
+                match validate_convert_url(Some(attempt.url().as_str()), allowed_hosts.as_deref()) {
+                    Ok(_) => attempt.follow(),
+                    Err(message) => attempt.error(format!("redirected to a bad url: {message}")),
+                }
+            }))
+            .build()
+            .expect("should be able to build the HTTP client");
+
+        if config.cat_api_key.is_none() {
+            warn!("$CAT_API_KEY is not set, calling the Cat API anonymously");
+        }
+
+        ServerState {
+            client,
+            convert_client,
+            art_cache: TtlCache::new(config.art_cache_ttl, config.art_cache_entries),
+            download_cache: BoundedCache::new(config.download_cache_entries)
+                .with_max_weight(config.download_cache_bytes, Vec::len),
+            retry: config.upstream_retry,
+            metrics: metrics_handle(),
+            cat_api_key: config.cat_api_key.clone(),
+            verbose_errors: config.verbose_errors,
+            default_art_options: ArtOptions {
+                characters: config.ascii_characters.clone(),
+                aspect_ratio: config.aspect_ratio,
+                ..Default::default()
+            },
+            convert_allowed_hosts: config.convert_allowed_hosts.clone(),
+            max_download_bytes: config.max_download_bytes,
+            max_image_dimension: config.max_image_dimension,
+            max_output_chars: config.max_output_chars,
+            art_cache_control: HeaderValue::from_str(&format!(
+                "public, max-age={}",
+                config.art_max_age.as_secs()
+            ))
+            .expect("should be a valid header value"),
+            pipeline_timeout: config.pipeline_timeout,
+            cat_api_url: config.cat_api_url.clone(),
+            dog_api_url: config.dog_api_url.clone(),
+            art_permits: Arc::new(Semaphore::new(config.max_in_flight)),
+            cat_images: Box::new(CatApi),
+            cat_api_breaker: CircuitBreaker::new(
+                config.circuit_breaker_threshold,
+                config.circuit_breaker_cooldown,
+            ),
+            cat_api_quota_reset: Mutex::new(None),
+            cat_api_last_success: Mutex::new(None),
+            honeycomb_enabled: config.honeycomb_api_key.is_some(),
+            cat_api_allow_http: config.cat_api_allow_http,
+            fallback_enabled: config.fallback_enabled,
+            stream_interval: config.stream_interval,
+            archive_dir: config.archive_dir.clone(),
+            stats: Stats::default(),
+        }
+    }
+}
+
+fn main() {
+    let args = CliArgs::parse(std::env::args().skip(1)).unwrap_or_else(|e| {
+        eprintln!("{e}\n{USAGE}");
+        std::process::exit(2);
+    });
+    let config = Config::from_env().unwrap_or_else(|e| {
+        eprintln!("{e:?}");
+        std::process::exit(1);
+    });
+    // Before there are other threads to read the environment.
+    force_ansi_colors();
+
+    let runtime = build_runtime(&config).expect("should be able to start the tokio runtime");
+    runtime.block_on(run(args, config));
+}
+
+/// Built by hand so conversion-heavy deployments can size the pools.
+fn build_runtime(config: &Config) -> std::io::Result<tokio::runtime::Runtime> {
+    tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(config.tokio_workers.get())
+        .max_blocking_threads(config.tokio_blocking_threads.get())
+        .enable_all()
+        .build()
+}
+
+/// artem only colors ANSI art when our stdout is a terminal advertising
+/// 24-bit color, which a server's never is. The art is for the client's
+/// terminal, so color it regardless.
+fn force_ansi_colors() {
+    colored::control::set_override(true);
+    std::env::set_var("COLORTERM", "truecolor");
+}
+
+async fn run(args: CliArgs, config: Config) {
+    if args.once {
+        let state = ServerState::new(&config);
+        match render_once(&state, args.width.as_deref()).await {
+            Ok(art) => println!("{art}"),
+            Err(e) => {
+                eprintln!("{e:?}");
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let tls_config = match &config.tls {
+        Some(paths) => Some(load_tls_config(paths).await.unwrap_or_else(|e| {
+            eprintln!("{e:?}");
+            std::process::exit(1);
+        })),
+        None => None,
+    };
+
+    let (log_filter, log_filter_handle) = reload::Layer::new(config.log_filter.clone());
+    tracing_subscriber::registry()
+        .with(log_filter)
+        .with(log_output(config.log_format))
+        .init();
+    let reload_log_filter: ReloadLogFilter =
+        Arc::new(move |filter| log_filter_handle.reload(filter).map_err(|e| e.to_string()));
+
+    let sentry_guard = init_sentry(config.sentry_dsn.as_deref());
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    let honeyguard = init_honeycomb(config.honeycomb_api_key.clone());
+
+    sampling::set_rates(config.trace_sample_rates.clone());
+    let state = Arc::new(ServerState::new(&config));
+    if config.prefetch_on_start {
+        tokio::spawn(prefetch(state.clone()));
+    }
+
+    let addr = config.listen_addr;
+    let make_service =
+        app(&config, state, reload_log_filter).into_make_service_with_connect_info::<SocketAddr>();
+    match tls_config {
+        Some(tls_config) => {
+            info!("Listening on {addr} with TLS");
+            let handle = axum_server::Handle::new();
+            tokio::spawn({
+                let handle = handle.clone();
+                async move {
+                    shutdown_signal().await;
+                    handle.graceful_shutdown(None);
+                }
+            });
+            axum_server::bind_rustls(addr, tls_config)
+                .http_config(
+                    axum_server::HttpConfig::new()
+                        .http2_only(config.http2_only)
+                        .build(),
+                )
+                .handle(handle)
+                .serve(make_service)
+                .await
+                .unwrap();
+        }
+        None => {
+            info!("Listening on {addr}");
+            axum::Server::bind(&addr)
+                .http2_only(config.http2_only)
+                .serve(make_service)
+                .with_graceful_shutdown(shutdown_signal())
+                .await
+                .unwrap();
+        }
+    }
+
+    // In-flight requests have drained by now, make sure their traces and
+    // any pending Sentry events make it out before we exit.
+    info!("Server stopped, flushing telemetry");
+    global::shutdown_tracer_provider();
+    drop(honeyguard);
+    drop(sentry_guard);
 }
 
-#[tokio::main]
-async fn main() {
-    let _guard = sentry::init((
-        std::env::var("SENTRY_DSN").expect("$SENTRY_DSN must be set"),
+/// Writes log lines in `format`. Boxed so either formatter fits under the
+/// same reloadable filter.
+fn log_output<S>(format: LogFormat) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: tracing::Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
+{
+    match format {
+        LogFormat::Json => tracing_subscriber::fmt::layer().json().boxed(),
+        LogFormat::Pretty => tracing_subscriber::fmt::layer().pretty().boxed(),
+    }
+}
+
+/// Reports panics and errors to Sentry, if we have somewhere to send them.
+fn init_sentry(dsn: Option<&str>) -> Option<sentry::ClientInitGuard> {
+    let Some(dsn) = dsn else {
+        warn!("$SENTRY_DSN is not set, errors won't be reported to Sentry");
+        return None;
+    };
+
+    Some(sentry::init((
+        dsn,
         sentry::ClientOptions {
             release: sentry::release_name!(),
             ..Default::default()
         },
+    )))
+}
+
+/// Exports traces to Honeycomb. Without an API key, the global tracer stays
+/// a no-op and only the JSON logs on stdout are left to go by.
+fn init_honeycomb(api_key: Option<String>) -> Option<impl Sized> {
+    let Some(api_key) = api_key else {
+        warn!("$HONEYCOMB_API_KEY is not set, traces won't be exported to Honeycomb");
+        return None;
+    };
+
+    let (honeyguard, _tracer) = opentelemetry_honeycomb::new_pipeline(api_key, "catscii".into())
+        .install()
+        .unwrap();
+    Some(honeyguard)
+}
+
+/// Routes that shouldn't be open to the whole internet, behind Basic auth if
+/// it's configured.
+fn admin_routes(auth: BasicAuth, reload_log_filter: ReloadLogFilter) -> Router<Arc<ServerState>> {
+    let router = Router::new()
+        .route("/metrics", get(metrics_get))
+        .route("/panic", get(panic_get));
+    // Anyone could turn on trace logging for everything, so this one needs
+    // credentials to exist at all.
+    let router = if auth.is_enabled() {
+        router.route(
+            "/log-level",
+            put(log_level_put).with_state(reload_log_filter),
+        )
+    } else {
+        warn!(
+            "$BASIC_AUTH_USER is not set, /metrics and /panic are open to anyone and \
+             /log-level is disabled"
+        );
+        router
+    };
+
+    router.route_layer(middleware::from_fn_with_state(
+        Arc::new(auth),
+        require_basic_auth,
+    ))
+}
+
+/// Every route and middleware, in front of `state`.
+fn app(config: &Config, state: Arc<ServerState>, reload_log_filter: ReloadLogFilter) -> Router {
+    let rate_limiter = Arc::new(RateLimiter::new(
+        config.rate_limit_per_second,
+        config.rate_limit_burst,
+        config.trusted_proxy_hops,
     ));
 
-    let (_honeyguard, _tracer) = opentelemetry_honeycomb::new_pipeline(
-        std::env::var("HONEYCOMB_API_KEY").expect("$HONEYCOMB_API_KEY should be set"),
-        "catscii".into(),
-    )
-    .install()
-    .unwrap();
+    // Everything that downloads and converts a picture, sharing one budget
+    // per client.
+    let art_routes = Router::new()
+        .route("/", get(root_get))
+        .route("/cat/:id", get(cat_by_id_get))
+        .route("/dog", get(dog_get))
+        .route("/convert", get(convert_get))
+        .route("/stream", get(stream_get))
+        .route("/ws", get(ws_get))
+        .route_layer(middleware::from_fn_with_state(rate_limiter, rate_limit));
+
+    let app = Router::new()
+        .merge(art_routes)
+        .route("/random", get(random_get))
+        .route("/health", get(health_get))
+        .route("/ready", get(ready_get))
+        .route("/version", get(version_get))
+        .route("/stats", get(stats_get))
+        .route("/cat.png", get(cat_png_get))
+        .route("/favicon.ico", get(favicon_get))
+        .merge(admin_routes(
+            BasicAuth::new(config.basic_auth.as_ref()),
+            reload_log_filter,
+        ));
+    #[cfg(feature = "openapi")]
+    let app = app.route("/openapi.json", get(openapi::openapi_get));
+    let app = app
+        .fallback(not_found)
+        .with_state(state)
+        .layer(middleware::from_fn(catch_panic));
+    // Nested so that axum has added `Allow` to 405s by the time
+    // `method_not_allowed` sees them, which isn't the case for layers on the
+    // routes themselves.
+    Router::new()
+        .fallback_service(app)
+        .layer(middleware::from_fn(method_not_allowed))
+        .layer(middleware::from_fn_with_state(
+            config.request_timeout,
+            request_timeout,
+        ))
+        .layer(cors_layer(config.cors_allowed_origins.clone()))
+        // The per-character markup in HTML art compresses really well.
+        .layer(CompressionLayer::new())
+        .layer(middleware::from_fn_with_state(
+            config.trusted_proxy_hops,
+            access_log::access_log,
+        ))
+}
 
-    let quit_sig = async {
+/// Reads the certificate chain and private key, so bad paths are reported at
+/// startup rather than on the first handshake.
+async fn load_tls_config(paths: &TlsPaths) -> color_eyre::Result<RustlsConfig> {
+    RustlsConfig::from_pem_file(&paths.cert, &paths.key)
+        .await
+        .wrap_err_with(|| {
+            format!(
+                "Failed to load TLS certificate {} and key {}",
+                paths.cert.display(),
+                paths.key.display()
+            )
+        })
+}
+
+/// Warms up connections and caches with one cat while the server starts
+/// taking requests. Failing is fine, requests will just be a bit slower.
+async fn prefetch(state: Arc<ServerState>) {
+    let span = global::tracer("").start("prefetch");
+    let result = get_ascii_art(&state, &CatSource::default(), &state.default_art_options)
+        .with_context(Context::current_with_span(span))
+        .await;
+    match result {
+        Ok(_) => info!("Prefetched a cat"),
+        Err(e) => warn!("Prefetching a cat failed: {e:#}"),
+    }
+}
+
+/// Renders one cat with ANSI colors for the terminal, for `--once`.
+async fn render_once(state: &ServerState, width: Option<&str>) -> color_eyre::Result<String> {
+    let width = width
+        .map(parse_width)
+        .transpose()
+        .map_err(|message| color_eyre::eyre::eyre!("{message}"))?;
+    let options = ArtOptions {
+        format: ArtFormat::Ansi,
+        width,
+        ..state.default_art_options.clone()
+    };
+    let art = get_ascii_art(state, &CatSource::default(), &options).await?;
+    Ok(art.text)
+}
+
+/// Allows `origins` to fetch art from a browser. Without an explicit list,
+/// debug builds allow any origin and release builds none.
+fn cors_layer(origins: Option<Vec<HeaderValue>>) -> CorsLayer {
+    let layer = CorsLayer::new()
+        .allow_methods([Method::GET])
+        .allow_headers([header::ACCEPT])
+        // The layer replaces whatever `Vary` a handler sent, so it carries
+        // the art's along with its own.
+        .vary(
+            cors::preflight_request_headers()
+                .chain(ART_VARY)
+                .collect::<Vec<_>>(),
+        );
+
+    match origins {
+        Some(origins) => layer.allow_origin(AllowOrigin::list(origins)),
+        None if cfg!(debug_assertions) => layer.allow_origin(AllowOrigin::any()),
+        None => layer,
+    }
+}
+
+/// Resolves on Ctrl-C, or on SIGTERM when running on Unix (which is what
+/// container runtimes send when stopping us).
+async fn shutdown_signal() {
+    let ctrl_c = async {
         _ = tokio::signal::ctrl_c().await;
-        warn!("Initiating graceful shutdown");
     };
 
-    let filter = Targets::from_str(std::env::var("RUST_LOG").as_deref().unwrap_or("info"))
-        .expect("RUST_LOG should be a valid tracing filter");
-    tracing_subscriber::fmt()
-        .with_max_level(Level::TRACE)
-        .json()
-        .finish()
-        .with(filter)
-        .init();
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("should be able to install a SIGTERM handler")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
 
-    let state = ServerState {
-        client: Default::default(),
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+    warn!("Initiating graceful shutdown");
+}
+
+#[derive(Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams))]
+#[cfg_attr(feature = "openapi", into_params(parameter_in = Query))]
+struct HealthQuery {
+    /// Report on telemetry, the Cat API and caches too.
+    verbose: Option<String>,
+}
+
+/// Liveness check. Deliberately doesn't trace or touch the network so it
+/// stays cheap no matter how often the orchestrator polls it, verbose or
+/// not.
+#[cfg_attr(feature = "openapi", utoipa::path(
+    get,
+    path = "/health",
+    params(HealthQuery),
+    responses((
+        status = 200,
+        description = "The server is up. With `?verbose=true` the body is a `HealthDetail` \
+            instead.",
+        body = Health,
+    )),
+))]
+async fn health_get(
+    headers: HeaderMap,
+    Query(query): Query<HealthQuery>,
+    State(state): State<Arc<ServerState>>,
+) -> Response<BoxBody> {
+    let verbose = match parse_bool_param("verbose", query.verbose.as_deref()) {
+        Ok(verbose) => verbose.unwrap_or(false),
+        Err(message) => {
+            return error_body(
+                StatusCode::BAD_REQUEST,
+                &message,
+                TraceId::INVALID,
+                &headers,
+            )
+        }
     };
+    if !verbose {
+        return Json(Health { status: "ok" }).into_response();
+    }
 
-    let app = Router::new()
-        .route("/", get(root_get))
-        .route("/panic", get(|| async { panic!("This is a test panic") }))
-        .with_state(state);
-
-    let addr = "0.0.0.0:8080".parse().unwrap();
-    info!("Listening on {addr}");
-    axum::Server::bind(&addr)
-        .serve(app.into_make_service())
-        .with_graceful_shutdown(quit_sig)
+    let cat_api_last_success = state
+        .cat_api_last_success
+        .lock()
+        .unwrap()
+        .and_then(|at| at.duration_since(SystemTime::UNIX_EPOCH).ok())
+        .map(|since_epoch| since_epoch.as_secs());
+    Json(HealthDetail {
+        status: "ok",
+        honeycomb: state.honeycomb_enabled,
+        sentry: sentry::Hub::current()
+            .client()
+            .is_some_and(|client| client.is_enabled()),
+        cat_api_last_success,
+        art_cache_entries: state.art_cache.len(),
+        download_cache_entries: state.download_cache.len(),
+    })
+    .into_response()
+}
+
+#[derive(Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+struct Health {
+    status: &'static str,
+}
+
+#[derive(Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+struct HealthDetail {
+    status: &'static str,
+    /// Whether traces are exported.
+    honeycomb: bool,
+    /// Whether errors are reported.
+    sentry: bool,
+    /// Seconds since the Unix epoch, missing if it hasn't answered yet.
+    cat_api_last_success: Option<u64>,
+    art_cache_entries: usize,
+    download_cache_entries: usize,
+}
+
+/// Which build is running, for checking what got deployed. Untraced like
+/// `/health`.
+#[cfg_attr(feature = "openapi", utoipa::path(
+    get,
+    path = "/version",
+    responses((status = 200, description = "The running build", body = Version)),
+))]
+async fn version_get() -> Json<Version> {
+    Json(Version {
+        version: env!("CARGO_PKG_VERSION"),
+        git_sha: env!("CATSCII_GIT_SHA"),
+        build_timestamp: env!("CATSCII_BUILD_TIMESTAMP")
+            .parse()
+            .expect("build.rs should set a numeric timestamp"),
+    })
+}
+
+#[derive(Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+struct Version {
+    version: &'static str,
+    git_sha: &'static str,
+    /// Seconds since the Unix epoch.
+    build_timestamp: u64,
+}
+
+/// Browsers ask for this on every page view, so it's embedded and untraced.
+async fn favicon_get() -> impl IntoResponse {
+    (
+        [
+            (header::CONTENT_TYPE, "image/x-icon"),
+            (header::CACHE_CONTROL, "public, max-age=604800, immutable"),
+        ],
+        include_bytes!("../assets/favicon.ico").as_slice(),
+    )
+}
+
+/// How long a readiness probe waits on the Cat API before calling it down.
+const READY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Readiness check: only ready to take traffic if the Cat API answers.
+#[cfg_attr(feature = "openapi", utoipa::path(
+    get,
+    path = "/ready",
+    responses(
+        (status = 200, description = "The Cat API answers", body = Health),
+        (status = 503, description = "The Cat API is unreachable", body = Health),
+    ),
+))]
+async fn ready_get(State(state): State<Arc<ServerState>>) -> (StatusCode, Json<Health>) {
+    let reachable = state
+        .client
+        .head(state.cat_api_url.clone())
+        .timeout(READY_TIMEOUT)
+        .send()
         .await
-        .unwrap();
+        .map(|res| !res.status().is_server_error())
+        .unwrap_or(false);
+
+    if reachable {
+        (StatusCode::OK, Json(Health { status: "ok" }))
+    } else {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(Health {
+                status: "cat api unreachable",
+            }),
+        )
+    }
+}
+
+/// Swaps the log filter of the running subscriber.
+type ReloadLogFilter = Arc<dyn Fn(Targets) -> Result<(), String> + Send + Sync>;
+
+/// Replaces the `$RUST_LOG` filter until the next restart, with a body in
+/// the same syntax.
+async fn log_level_put(
+    State(reload_log_filter): State<ReloadLogFilter>,
+    headers: HeaderMap,
+    body: String,
+) -> Response<BoxBody> {
+    let filter = match body.trim().parse::<Targets>() {
+        Ok(filter) => filter,
+        Err(e) => {
+            return error_body(
+                StatusCode::BAD_REQUEST,
+                &format!("invalid log filter: {e}"),
+                TraceId::INVALID,
+                &headers,
+            )
+        }
+    };
+
+    match reload_log_filter(filter) {
+        Ok(()) => {
+            info!("Log filter changed to {:?}", body.trim());
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(e) => error_body(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("failed to change the log filter: {e}"),
+            TraceId::INVALID,
+            &headers,
+        ),
+    }
+}
+
+/// Totals since startup, as JSON.
+#[cfg_attr(feature = "openapi", utoipa::path(
+    get,
+    path = "/stats",
+    responses((status = 200, description = "Request totals", body = stats::StatsSnapshot)),
+))]
+async fn stats_get(State(state): State<Arc<ServerState>>) -> Json<stats::StatsSnapshot> {
+    Json(state.stats.snapshot(stats::CacheSizes {
+        art_cache_entries: state.art_cache.len(),
+        download_cache_entries: state.download_cache.len(),
+        download_cache_bytes: state.download_cache.weight(),
+    }))
+}
+
+/// The handle to the process's one metrics recorder, installed the first
+/// time it's asked for (tests build a state each, and share it).
+fn metrics_handle() -> PrometheusHandle {
+    static HANDLE: std::sync::OnceLock<PrometheusHandle> = std::sync::OnceLock::new();
+    HANDLE
+        .get_or_init(|| {
+            let recorder = PrometheusBuilder::new()
+                .set_buckets(&[0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0])
+                .expect("histogram buckets should be valid")
+                .build_recorder();
+            let handle = recorder.handle();
+            if metrics::set_boxed_recorder(Box::new(recorder)).is_err() {
+                warn!("A metrics recorder is already installed, /metrics will be empty");
+            }
+            handle
+        })
+        .clone()
+}
+
+/// Prometheus scrape endpoint.
+async fn metrics_get(State(state): State<Arc<ServerState>>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.metrics.render(),
+    )
+}
+
+/// Checks that panics get reported and answered properly.
+async fn panic_get() {
+    panic!("This is a test panic")
+}
+
+#[derive(Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams))]
+#[cfg_attr(feature = "openapi", into_params(parameter_in = Query))]
+struct RootQuery {
+    width: Option<String>,
+    /// `small`, `medium` or `large`, instead of picking a `width`.
+    size: Option<String>,
+    breed: Option<String>,
+    color: Option<String>,
+    grayscale: Option<String>,
+    invert: Option<String>,
+    chars: Option<String>,
+    raw: Option<String>,
+    /// Overrides the format picked from `Accept`.
+    format: Option<String>,
+    /// Vertical squash factor, like `0.8`.
+    aspect: Option<String>,
+    dither: Option<String>,
+    /// Number of colors to reduce the picture to.
+    colors: Option<String>,
+    border: Option<String>,
+    /// `light` or `dark`, overriding the browser's preference.
+    theme: Option<String>,
+    /// Named bundle of characters, color and inversion, like `blocks`.
+    preset: Option<String>,
+    edges: Option<String>,
+    /// Serve plain text art as a file to save.
+    download: Option<String>,
+    /// Same as `format`, reads better in `?target=ansi`.
+    target: Option<String>,
+    mode: Option<String>,
+    count: Option<String>,
+    seed: Option<String>,
+    /// Image to convert, only used by `/convert`.
+    url: Option<String>,
 }
 
-async fn root_get(headers: HeaderMap, State(state): State<ServerState>) -> Response<BoxBody> {
+/// A random cat, as HTML, plain text, ANSI or JSON depending on `Accept`.
+#[cfg_attr(feature = "openapi", utoipa::path(
+    get,
+    path = "/",
+    params(RootQuery),
+    responses(
+        (status = 200, description = "Cat art"),
+        (status = 400, description = "Invalid query parameters"),
+        (status = 404, description = "Unknown breed"),
+        (status = 429, description = "Rate limited"),
+        (status = 502, description = "Upstream failed"),
+    ),
+))]
+async fn root_get(
+    headers: HeaderMap,
+    Query(query): Query<RootQuery>,
+    State(state): State<Arc<ServerState>>,
+) -> Response<BoxBody> {
+    let mut span = start_request_span("root_get", &headers);
+
+    let breed = match query.breed.clone().map(validate_breed).transpose() {
+        Ok(breed) => breed,
+        Err(message) => return bad_request(&mut span, &headers, message),
+    };
+    if let Some(breed) = &breed {
+        span.set_attribute(KeyValue::new("breed", breed.clone()));
+    }
+    let seed = match query.seed.clone().map(validate_seed).transpose() {
+        Ok(seed) => seed,
+        Err(message) => return bad_request(&mut span, &headers, message),
+    };
+    if let Some(seed) = &seed {
+        span.set_attribute(KeyValue::new("seed", seed.clone()));
+    }
+
+    art_get(span, &headers, &query, state, CatSource { breed, seed }).await
+}
+
+/// One particular cat, for sharing a link to it.
+#[cfg_attr(feature = "openapi", utoipa::path(
+    get,
+    path = "/cat/{id}",
+    params(("id" = String, Path, description = "Cat API image id"), RootQuery),
+    responses(
+        (status = 200, description = "Cat art"),
+        (status = 400, description = "Invalid query parameters"),
+        (status = 404, description = "No cat with that id"),
+    ),
+))]
+async fn cat_by_id_get(
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    Query(query): Query<RootQuery>,
+    State(state): State<Arc<ServerState>>,
+) -> Response<BoxBody> {
+    let mut span = start_request_span("cat_by_id_get", &headers);
+
+    // Malformed ids can't exist, no need to ask.
+    let id = match validate_image_id(id) {
+        Ok(id) => id,
+        Err(message) => {
+            span.set_status(Status::Error {
+                description: message.clone().into(),
+            });
+            return error_body(
+                StatusCode::NOT_FOUND,
+                &message,
+                span.span_context().trace_id(),
+                &headers,
+            );
+        }
+    };
+    span.set_attribute(KeyValue::new("image_id", id.clone()));
+    if query.breed.is_some() || query.seed.is_some() {
+        return bad_request(
+            &mut span,
+            &headers,
+            "breed and seed don't apply to a specific cat".to_owned(),
+        );
+    }
+
+    art_get(span, &headers, &query, state, CatByIdSource { id }).await
+}
+
+/// Sends the browser straight to a random cat picture, without converting
+/// anything.
+#[cfg_attr(feature = "openapi", utoipa::path(
+    get,
+    path = "/random",
+    responses(
+        (status = 302, description = "Redirect to a cat picture"),
+        (status = 502, description = "Upstream failed"),
+    ),
+))]
+async fn random_get(
+    headers: HeaderMap,
+    State(state): State<Arc<ServerState>>,
+) -> Response<BoxBody> {
+    let span = start_request_span("random_get", &headers);
+    async {
+        let location = state
+            .cat_images
+            .random_image_url(&state, None, None)
+            .await
+            .and_then(|url| {
+                HeaderValue::from_str(&url)
+                    .wrap_err_with(|| format!("The Cat API sent an invalid URL: {url:?}"))
+            });
+        match location {
+            Ok(location) => (
+                StatusCode::FOUND,
+                [
+                    (header::LOCATION, location),
+                    // Every visit should land on a new cat.
+                    (header::CACHE_CONTROL, HeaderValue::from_static("no-store")),
+                ],
+            )
+                .into_response(),
+            Err(e) => error_response(&e, &headers, state.verbose_errors),
+        }
+    }
+    .with_context(Context::current_with_span(span))
+    .await
+}
+
+/// A random dog, same formats as `/`.
+#[cfg_attr(feature = "openapi", utoipa::path(
+    get,
+    path = "/dog",
+    params(RootQuery),
+    responses(
+        (status = 200, description = "Dog art"),
+        (status = 400, description = "Invalid query parameters"),
+    ),
+))]
+async fn dog_get(
+    headers: HeaderMap,
+    Query(query): Query<RootQuery>,
+    State(state): State<Arc<ServerState>>,
+) -> Response<BoxBody> {
+    let mut span = start_request_span("dog_get", &headers);
+
+    if query.breed.is_some() || query.seed.is_some() {
+        return bad_request(
+            &mut span,
+            &headers,
+            "breed and seed are only supported for cats".to_owned(),
+        );
+    }
+
+    art_get(span, &headers, &query, state, DogSource).await
+}
+
+/// Converts any image on the web, not just animals.
+#[cfg_attr(feature = "openapi", utoipa::path(
+    get,
+    path = "/convert",
+    params(RootQuery),
+    responses(
+        (status = 200, description = "Art of the image at `url`"),
+        (status = 400, description = "Missing, invalid or disallowed `url`"),
+        (status = 415, description = "Not an image format we can read"),
+    ),
+))]
+async fn convert_get(
+    headers: HeaderMap,
+    Query(query): Query<RootQuery>,
+    State(state): State<Arc<ServerState>>,
+) -> Response<BoxBody> {
+    let mut span = start_request_span("convert_get", &headers);
+
+    let url =
+        match validate_convert_url(query.url.as_deref(), state.convert_allowed_hosts.as_deref()) {
+            Ok(url) => url,
+            Err(message) => return bad_request(&mut span, &headers, message),
+        };
+    span.set_attribute(KeyValue::new("source_url", url.to_string()));
+
+    art_get(span, &headers, &query, state, UrlSource { url }).await
+}
+
+/// Sends a new cat every `$STREAM_INTERVAL_SECS` as server-sent events, as
+/// long as the client stays connected. Each cat gets a trace of its own,
+/// since a stream can last for hours.
+async fn stream_get(
+    headers: HeaderMap,
+    Query(query): Query<RootQuery>,
+    State(state): State<Arc<ServerState>>,
+) -> Response<BoxBody> {
+    let options = match ArtOptions::from_request(&state.default_art_options, &headers, &query) {
+        Ok(options) => ArtOptions {
+            format: ArtFormat::PlainText,
+            ..options
+        },
+        Err(message) => {
+            return bad_request(
+                &mut start_request_span("stream_get", &headers),
+                &headers,
+                message,
+            )
+        }
+    };
+
+    let events = futures_util::stream::unfold(true, move |first| {
+        let state = state.clone();
+        let options = options.clone();
+        async move {
+            if !first {
+                tokio::time::sleep(state.stream_interval).await;
+            }
+            let span = global::tracer("").start("stream_cat");
+            let event = async {
+                match single_art(&state, &CatSource::default(), &options).await {
+                    Ok(art) => Event::default().event("cat").json_data(ArtJson {
+                        art: &art.text,
+                        source_url: art.source_url.as_deref(),
+                        width: art.width,
+                        height: art.height,
+                    }),
+                    Err(message) => Ok(Event::default().event("error").data(message)),
+                }
+            }
+            .with_context(Context::current_with_span(span))
+            .await;
+            Some((event, false))
+        }
+    });
+
+    Sse::new(events)
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+/// Hands out cats over a WebSocket, one per command.
+async fn ws_get(ws: WebSocketUpgrade, State(state): State<Arc<ServerState>>) -> Response<BoxBody> {
+    ws.on_upgrade(move |socket| ws_session(socket, state))
+}
+
+/// What clients send over `/ws`, like `{"action":"cat","width":120}`.
+#[derive(Deserialize)]
+#[serde(tag = "action", rename_all = "lowercase")]
+enum WsCommand {
+    Cat {
+        width: Option<u32>,
+        breed: Option<String>,
+    },
+}
+
+async fn ws_session(mut socket: WebSocket, state: Arc<ServerState>) {
+    while let Some(Ok(message)) = socket.recv().await {
+        let command = match message {
+            Message::Text(command) => command,
+            Message::Close(_) => break,
+            // Pings are answered for us, and we don't speak binary.
+            _ => continue,
+        };
+
+        let span = global::tracer("").start("ws_cat");
+        let reply = ws_reply(&state, &command)
+            .with_context(Context::current_with_span(span))
+            .await;
+        if socket.send(Message::Text(reply)).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Runs one command, answering with plain text art or, so that one bad
+/// command doesn't cost the client its connection, a JSON error.
+async fn ws_reply(state: &ServerState, command: &str) -> String {
+    let result = async {
+        let WsCommand::Cat { width, breed } =
+            serde_json::from_str(command).map_err(|e| format!("invalid command: {e}"))?;
+        let options = ArtOptions {
+            format: ArtFormat::PlainText,
+            width: width.map(validate_width).transpose()?,
+            ..state.default_art_options.clone()
+        };
+        let source = CatSource {
+            breed: breed.map(validate_breed).transpose()?,
+            ..Default::default()
+        };
+        single_art(state, &source, &options).await
+    }
+    .await;
+
+    match result {
+        Ok(art) => art.text,
+        Err(message) => {
+            get_active_span(|span| {
+                span.set_status(Status::Error {
+                    description: message.clone().into(),
+                })
+            });
+            serde_json::json!({ "error": message }).to_string()
+        }
+    }
+}
+
+/// Only lets through http(s) URLs, to hosts in `allowed_hosts` if given, so
+/// `/convert` can't be used to poke at arbitrary services from our network.
+fn validate_convert_url(
+    raw: Option<&str>,
+    allowed_hosts: Option<&[String]>,
+) -> Result<reqwest::Url, String> {
+    let raw = raw.ok_or("url is required")?;
+    let url = reqwest::Url::parse(raw).map_err(|_| "url must be a valid absolute URL")?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err("url must use http or https".to_owned());
+    }
+    let host = url.host_str().ok_or("url must have a host")?;
+    if let Some(allowed_hosts) = allowed_hosts {
+        if !allowed_hosts
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(host))
+        {
+            return Err(format!("host {host:?} is not allowed"));
+        }
+    }
+    Ok(url)
+}
+
+/// Redirects followed for `/convert`, same as reqwest's default policy.
+const MAX_REDIRECTS: usize = 10;
+
+/// Answers `504 Gateway Timeout` to requests that take longer than `timeout`.
+///
+/// The handler's own spans end unmarked when its future is dropped, so an
+/// errored span is recorded in the caller's trace to show what happened.
+async fn request_timeout<B>(
+    State(timeout): State<Duration>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response<BoxBody> {
+    let headers = request.headers().clone();
+    match tokio::time::timeout(timeout, next.run(request)).await {
+        Ok(response) => response,
+        Err(_) => {
+            let mut span = start_request_span("request_timeout", &headers);
+            span.set_status(Status::Error {
+                description: format!("request took longer than {timeout:?}").into(),
+            });
+            error_body(
+                StatusCode::GATEWAY_TIMEOUT,
+                "Request timed out",
+                span.span_context().trace_id(),
+                &headers,
+            )
+        }
+    }
+}
+
+/// Reads W3C `traceparent`/`tracestate` request headers.
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|v| v.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|k| k.as_str()).collect()
+    }
+}
+
+/// Starts the top-level span for a request, as a child of the caller's
+/// trace if they sent one along.
+fn start_request_span(name: &'static str, headers: &HeaderMap) -> BoxedSpan {
+    let parent_cx =
+        global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(headers)));
     let tracer = global::tracer("");
-    let mut span = tracer.start("root_get");
+    let mut builder = tracer.span_builder(name);
+    // Callers that already decided get traced their way.
+    if !parent_cx.span().span_context().is_valid() {
+        builder.sampling_result = sampling::decide(name);
+    }
+    let span = tracer.build_with_context(builder, &parent_cx);
+    access_log::record_trace_id(span.span_context().trace_id());
+    span
+}
+
+/// How long an art request may queue for a permit before getting a 503.
+const PERMIT_WAIT: Duration = Duration::from_millis(500);
+
+/// The parts of serving art that don't depend on which animal we're after.
+async fn art_get(
+    mut span: BoxedSpan,
+    headers: &HeaderMap,
+    query: &RootQuery,
+    state: Arc<ServerState>,
+    source: impl AnimalSource,
+) -> Response<BoxBody> {
     span.set_attribute(KeyValue::new(
         "user_agent",
         headers
@@ -82,100 +1212,4083 @@ async fn root_get(headers: HeaderMap, State(state): State<ServerState>) -> Respo
             .unwrap_or_default(),
     ));
 
-    root_get_inner(state)
+    let options = match ArtOptions::from_request(&state.default_art_options, headers, query) {
+        Ok(options) => options,
+        Err(message) => return bad_request(&mut span, headers, message),
+    };
+    // Downloads are saved as .txt, so that's what they get.
+    let download = match parse_bool_param("download", query.download.as_deref()) {
+        Ok(download) => download.unwrap_or(false),
+        Err(message) => return bad_request(&mut span, headers, message),
+    };
+    let options = if download {
+        ArtOptions {
+            format: ArtFormat::PlainText,
+            ..options
+        }
+    } else {
+        options
+    };
+    span.set_attribute(KeyValue::new("format", options.format.name()));
+    span.set_attribute(KeyValue::new(
+        "color_mode",
+        if options.color { "color" } else { "grayscale" },
+    ));
+    span.set_attribute(KeyValue::new("invert", options.invert));
+    if let Some(width) = options.width {
+        span.set_attribute(KeyValue::new("requested_width", width as i64));
+    }
+    let count = match query.count.as_deref().map(parse_count).transpose() {
+        Ok(count) => count.unwrap_or(1),
+        Err(message) => return bad_request(&mut span, headers, message),
+    };
+    span.set_attribute(KeyValue::new("count", count as i64));
+    if count > 1 && options.format == ArtFormat::Json {
+        return bad_request(
+            &mut span,
+            headers,
+            "count is not supported with format=json".to_owned(),
+        );
+    }
+
+    // A hub of our own keeps breadcrumbs from concurrent requests apart.
+    art_get_inner(state, headers, source, options, count, download)
         .with_context(Context::current_with_span(span))
+        .bind_hub(sentry::Hub::new_from_top(sentry::Hub::current()))
         .await
 }
 
-async fn root_get_inner(state: ServerState) -> Response<BoxBody> {
-    let tracer = global::tracer("");
+/// Waits a little for one of the in-flight slots every piece of art needs,
+/// `None` meaning we're too busy.
+async fn art_permit(state: &ServerState) -> Option<OwnedSemaphorePermit> {
+    // Owned, so the permit doesn't borrow `state` while it's handed on.
+    let permit = tokio::time::timeout(PERMIT_WAIT, state.art_permits.clone().acquire_owned()).await;
+    get_active_span(|span| {
+        span.set_attribute(KeyValue::new(
+            "permits.available",
+            state.art_permits.available_permits() as i64,
+        ));
+        if !matches!(permit, Ok(Ok(_))) {
+            span.set_status(Status::Error {
+                description: "too many requests in flight".into(),
+            });
+        }
+    });
+    permit.ok()?.ok()
+}
 
-    match get_cat_ascii_art(&state.client)
-        .with_context(Context::current_with_span(
-            tracer.start("get_cat_ascii_art"),
-        ))
+/// Makes `count` pieces of art within the pipeline deadline, counting the
+/// attempt in stats. Callers hold an [`art_permit`].
+async fn make_art(
+    state: &ServerState,
+    source: &dyn AnimalSource,
+    options: &ArtOptions,
+    count: u32,
+) -> color_eyre::Result<Vec<Art>> {
+    let tracer = global::tracer("");
+    let pipeline = async {
+        if count == 1 {
+            get_ascii_art(state, source, options)
+                .with_context(Context::current_with_span(tracer.start("get_ascii_art")))
+                .await
+                .map(|art| vec![art])
+        } else {
+            get_ascii_art_grid(state, source, options, count).await
+        }
+    };
+    // Running out of time drops the pipeline, cancelling any requests in
+    // flight. A conversion already on the blocking pool runs to completion,
+    // its result is just thrown away.
+    let result = tokio::time::timeout(state.pipeline_timeout, pipeline)
         .await
-    {
-        Ok(art) => (
-            StatusCode::OK,
-            [(header::CONTENT_TYPE, "text/html; charset=utf-8")],
-            art,
-        )
-            .into_response(),
+        .unwrap_or_else(|_| {
+            Err(PipelineTimeout {
+                budget: state.pipeline_timeout,
+            }
+            .into())
+        });
+    Stats::increment(&state.stats.requests);
+    if let Err(e) = &result {
+        metrics::increment_counter!("catscii_request_errors_total");
+        if matches!(
+            status_for_error(e).0,
+            StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT
+        ) {
+            Stats::increment(&state.stats.upstream_failures);
+        }
+    }
+    result
+}
+
+/// Counts how an art request that started at `start` went.
+fn record_art_outcome(state: &ServerState, outcome: &'static str, start: Instant) {
+    if outcome == "success" {
+        Stats::increment(&state.stats.successes);
+    }
+    metrics::increment_counter!("catscii_requests_total", "outcome" => outcome);
+    metrics::histogram!(
+        "catscii_request_duration_seconds",
+        start.elapsed().as_secs_f64()
+    );
+}
+
+/// One cat for `/stream` and `/ws`, with the same in-flight cap, deadline
+/// and bookkeeping as [`art_get`] but no fallback, failing with a message
+/// for the client.
+async fn single_art(
+    state: &ServerState,
+    source: &CatSource,
+    options: &ArtOptions,
+) -> Result<Art, String> {
+    let Some(_permit) = art_permit(state).await else {
+        return Err("Too busy right now, try again shortly".to_owned());
+    };
+    let start = Instant::now();
+    let result = make_art(state, source, options, 1).await;
+    record_art_outcome(
+        state,
+        if result.is_ok() { "success" } else { "error" },
+        start,
+    );
+    match result {
+        Ok(mut arts) => Ok(arts.remove(0)),
         Err(e) => {
             get_active_span(|span| {
                 span.set_status(Status::Error {
                     description: format!("{e}").into(),
                 })
             });
-            (StatusCode::INTERNAL_SERVER_ERROR, "Something went wrong").into_response()
+            Err(status_for_error(&e).1)
         }
     }
 }
 
-async fn get_cat_ascii_art(client: &reqwest::Client) -> color_eyre::Result<String> {
-    let tracer = global::tracer("");
+async fn art_get_inner(
+    state: Arc<ServerState>,
+    headers: &HeaderMap,
+    source: impl AnimalSource,
+    options: ArtOptions,
+    count: u32,
+    download: bool,
+) -> Response<BoxBody> {
+    // Wait a little for a slot to free up before turning the client away.
+    let Some(_permit) = art_permit(&state).await else {
+        let mut response = error_body(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Too busy right now, try again shortly",
+            get_active_span(|span| span.span_context().trace_id()),
+            headers,
+        );
+        response
+            .headers_mut()
+            .insert(header::RETRY_AFTER, HeaderValue::from_static("1"));
+        return response;
+    };
 
-    let image_url = get_cat_image_url(client)
-        .with_context(Context::current_with_span(
-            tracer.start("get_cat_image_url"),
-        ))
-        .await?;
+    let start = Instant::now();
+    let result = make_art(&state, &source, &options, count).await;
 
-    let image_bytes = download_file(client, &image_url)
-        .with_context(Context::current_with_span(tracer.start("download_file")))
-        .await?;
+    // Errors the client caused still get reported, there's no outage to
+    // cover up.
+    let mut fallback = false;
+    let result = match result {
+        Err(e) if state.fallback_enabled && status_for_error(&e).0.is_server_error() => {
+            get_active_span(|span| {
+                span.set_status(Status::Error {
+                    description: format!("{e}").into(),
+                });
+                span.set_attribute(KeyValue::new("fallback", true));
+            });
+            fallback = true;
+            render_fallback(&options).map(|art| vec![art])
+        }
+        result => result,
+    };
 
-    let image = tracer.in_span("image::load_from_memory", |cx| {
-        let img = image::load_from_memory(&image_bytes)?;
-        cx.span()
-            .set_attribute(KeyValue::new("width", img.width() as i64));
-        cx.span()
-            .set_attribute(KeyValue::new("height", img.height() as i64));
-        Ok::<_, color_eyre::eyre::Report>(img)
-    })?;
-
-    let ascii_art = tracer.in_span("artem::convert", |_cx| {
-        artem::convert(
-            image,
-            artem::options::OptionBuilder::new()
-                .target(artem::options::TargetType::HtmlFile(true, true))
-                .build(),
-        )
+    let outcome = match (&result, fallback) {
+        (Ok(_), false) => "success",
+        (Ok(_), true) => "fallback",
+        (Err(_), _) => "error",
+    };
+    record_art_outcome(&state, outcome, start);
+
+    let arts = match result {
+        Ok(arts) => arts,
+        Err(e) => return error_response(&e, headers, state.verbose_errors),
+    };
+
+    // Repeat cats come out of the art cache, so answering 304 costs us
+    // nothing but the Cat API call.
+    let etag = art_etag(&arts, &options, download);
+    if if_none_match(headers, &etag) {
+        get_active_span(|span| span.set_attribute(KeyValue::new("not_modified", true)));
+        let mut response = (StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response();
+        insert_cache_headers(&mut response, &state, fallback);
+        return response;
+    }
+
+    let mut response = if options.format == ArtFormat::Json {
+        let art = &arts[0];
+        Json(ArtJson {
+            art: &art.text,
+            source_url: art.source_url.as_deref(),
+            width: art.width,
+            height: art.height,
+        })
+        .into_response()
+    } else {
+        let art = join_arts(options.format, &arts);
+        if options.format == ArtFormat::Html && !options.raw {
+            (
+                StatusCode::OK,
+                [
+                    (header::CONTENT_TYPE, options.format.content_type()),
+                    // Asks browsers to tell us their color scheme next time.
+                    (
+                        HeaderName::from_static("accept-ch"),
+                        "Sec-CH-Prefers-Color-Scheme",
+                    ),
+                ],
+                page::wrap(&art, options.theme),
+            )
+                .into_response()
+        } else {
+            (
+                StatusCode::OK,
+                [(header::CONTENT_TYPE, options.format.content_type())],
+                art,
+            )
+                .into_response()
+        }
+    };
+
+    // A grid mixes several pictures, so there's no single size to report.
+    if let [art] = arts.as_slice() {
+        let response_headers = response.headers_mut();
+        response_headers.insert("x-image-width", HeaderValue::from(art.width));
+        response_headers.insert("x-image-height", HeaderValue::from(art.height));
+        // Not there for the fallback cat, which didn't come from anywhere.
+        if let Some(url) = art
+            .source_url
+            .as_deref()
+            .and_then(|url| HeaderValue::from_str(url).ok())
+        {
+            response_headers.insert("x-source-url", url);
+        }
+    }
+    response.headers_mut().insert(header::ETAG, etag);
+    insert_cache_headers(&mut response, &state, fallback);
+    if fallback {
+        response
+            .headers_mut()
+            .insert("x-fallback", HeaderValue::from_static("true"));
+    }
+    if download {
+        let disposition = format!(
+            "attachment; filename=\"{}.txt\"",
+            attachment_name(source.name())
+        );
+        response.headers_mut().insert(
+            header::CONTENT_DISPOSITION,
+            HeaderValue::from_str(&disposition).expect("sanitized filenames are valid headers"),
+        );
+    }
+    response
+}
+
+/// Headers that vary the art besides the URL, which caches must key on.
+const ART_VARY: [HeaderName; 2] = [
+    header::ACCEPT,
+    HeaderName::from_static("sec-ch-prefers-color-scheme"),
+];
+
+/// Lets browsers and CDNs keep art around for `$ART_MAX_AGE_SECS`, except
+/// the fallback cat, which should go away as soon as upstream is back. The
+/// matching `Vary` comes from [`cors_layer`].
+fn insert_cache_headers(response: &mut Response<BoxBody>, state: &ServerState, fallback: bool) {
+    let headers = response.headers_mut();
+    let cache_control = if fallback {
+        HeaderValue::from_static("no-store")
+    } else {
+        state.art_cache_control.clone()
+    };
+    headers.insert(header::CACHE_CONTROL, cache_control);
+}
+
+/// A strong validator for the response made from `arts` with `options`.
+fn art_etag(arts: &[Art], options: &ArtOptions, download: bool) -> HeaderValue {
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    for art in arts {
+        art.text.hash(&mut hasher);
+    }
+    options.hash(&mut hasher);
+    download.hash(&mut hasher);
+    HeaderValue::from_str(&format!("\"{:016x}\"", hasher.finish()))
+        .expect("hex digits are a valid header")
+}
+
+/// Whether the client's `If-None-Match` says it already has `etag`.
+fn if_none_match(headers: &HeaderMap, etag: &HeaderValue) -> bool {
+    let Some(etag) = etag.to_str().ok() else {
+        return false;
+    };
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|h| h.to_str().ok())
+        .flat_map(|h| h.split(','))
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+/// Longest filename stem we suggest for downloads.
+const MAX_ATTACHMENT_NAME: usize = 32;
+
+/// Keeps only characters that are safe in a quoted `Content-Disposition`
+/// filename on every OS.
+fn attachment_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == '_')
+        .take(MAX_ATTACHMENT_NAME)
+        .collect();
+    if name.is_empty() {
+        "art".to_owned()
+    } else {
+        name
+    }
+}
+
+/// What `?format=json` answers with.
+#[derive(Serialize)]
+struct ArtJson<'a> {
+    art: &'a str,
+    /// Missing when serving the fallback cat.
+    source_url: Option<&'a str>,
+    /// Dimensions of the source picture, in pixels.
+    width: u32,
+    height: u32,
+}
+
+/// Marks `span` as errored and answers with a 400 carrying `message`.
+fn bad_request(span: &mut BoxedSpan, headers: &HeaderMap, message: String) -> Response<BoxBody> {
+    span.set_status(Status::Error {
+        description: message.clone().into(),
     });
+    error_body(
+        StatusCode::BAD_REQUEST,
+        &message,
+        span.span_context().trace_id(),
+        headers,
+    )
+}
 
-    Ok(ascii_art)
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    error: &'a str,
+    /// Missing for errors raised outside of any trace, like unknown routes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    trace_id: Option<String>,
 }
 
-async fn get_cat_image_url(client: &reqwest::Client) -> color_eyre::Result<String> {
-    #[derive(Deserialize)]
-    struct CatImage {
-        url: String,
+/// Renders an error as JSON for clients that prefer it, plain text for
+/// everyone else, in their language when we can.
+fn error_body(
+    status: StatusCode,
+    message: &str,
+    trace_id: TraceId,
+    headers: &HeaderMap,
+) -> Response<BoxBody> {
+    let message = i18n::translate(message, i18n::Language::from_headers(headers));
+    let json_q = accept_quality(headers, "application/json");
+    if json_q > 0.0
+        && json_q > accept_quality(headers, "text/html")
+        && json_q > accept_quality(headers, "text/plain")
+    {
+        let body = ErrorBody {
+            error: message,
+            trace_id: (trace_id != TraceId::INVALID).then(|| trace_id.to_string()),
+        };
+        (status, Json(body)).into_response()
+    } else {
+        (status, message.to_owned()).into_response()
     }
+}
+
+/// Turns a panicking handler into a 500 instead of a dropped connection,
+/// with the trace the handler got to start and in the format the client
+/// asked for. Sentry's panic hook has already reported it by the time we
+/// get here.
+async fn catch_panic<B>(request: Request<B>, next: Next<B>) -> Response<BoxBody> {
+    let headers = request.headers().clone();
+    match std::panic::AssertUnwindSafe(next.run(request))
+        .catch_unwind()
+        .await
+    {
+        Ok(response) => response,
+        Err(panic) => {
+            let message = panic_message(panic.as_ref());
+            error!("Handler panicked: {message}");
 
-    let api_url = "http://api.thecatapi.com/v1/images/search";
+            error_body(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Something went wrong",
+                access_log::recorded_trace_id(),
+                &headers,
+            )
+        }
+    }
+}
 
-    let image = client
-        .get(api_url)
-        .send()
-        .await?
-        .error_for_status()?
-        .json::<Vec<CatImage>>()
-        .await?
-        .pop()
-        .ok_or_else(|| color_eyre::eyre::eyre!("The Cat API returned no images"))?;
+/// What a panic was called with, if it was a string like it usually is.
+fn panic_message(panic: &(dyn std::any::Any + Send)) -> String {
+    panic
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or_default()
+}
 
-    Ok(image.url)
+/// Answers requests for routes that don't exist. Not traced, since anyone
+/// can make up paths.
+async fn not_found(headers: HeaderMap) -> Response<BoxBody> {
+    error_body(
+        StatusCode::NOT_FOUND,
+        "Nothing here, try GET / for a cat",
+        TraceId::INVALID,
+        &headers,
+    )
 }
 
-async fn download_file(client: &reqwest::Client, url: &str) -> color_eyre::Result<Vec<u8>> {
-    let bytes = client
-        .get(url)
-        .send()
-        .await?
-        .error_for_status()?
-        .bytes()
+/// Replaces axum's empty 405 responses with an error body shaped like the
+/// rest of ours, keeping the `Allow` header.
+async fn method_not_allowed<B>(request: Request<B>, next: Next<B>) -> Response<BoxBody> {
+    let headers = request.headers().clone();
+    let response = next.run(request).await;
+    if response.status() != StatusCode::METHOD_NOT_ALLOWED {
+        return response;
+    }
+
+    let allow = response.headers().get(header::ALLOW);
+    let message = i18n::translate("Method not allowed", i18n::Language::from_headers(&headers));
+    let message = match allow.and_then(|allow| allow.to_str().ok()) {
+        Some(methods) => format!("{message} (Allow: {methods})"),
+        None => message.to_owned(),
+    };
+    let mut friendly = error_body(
+        StatusCode::METHOD_NOT_ALLOWED,
+        &message,
+        TraceId::INVALID,
+        &headers,
+    );
+    if let Some(allow) = allow {
+        friendly.headers_mut().insert(header::ALLOW, allow.clone());
+    }
+    friendly
+}
+
+/// Breed ids are short alphanumeric codes like `beng` or `abys`.
+fn validate_breed(breed: String) -> Result<String, String> {
+    if !breed.is_empty() && breed.len() <= 16 && breed.chars().all(|c| c.is_ascii_alphanumeric()) {
+        Ok(breed)
+    } else {
+        Err("breed must be a short alphanumeric breed id, like \"beng\"".to_owned())
+    }
+}
+
+/// Cat API image ids are short codes like `0XYvRd7oD`.
+fn validate_image_id(id: String) -> Result<String, String> {
+    if !id.is_empty()
+        && id.len() <= 32
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        Ok(id)
+    } else {
+        Err(CatApiError::UnknownImage(id).to_string())
+    }
+}
+
+/// Seeds are passed straight to the Cat API, so keep them boring.
+fn validate_seed(seed: String) -> Result<String, String> {
+    if !seed.is_empty() && seed.len() <= 64 && seed.chars().all(|c| c.is_ascii_alphanumeric()) {
+        Ok(seed)
+    } else {
+        Err("seed must be at most 64 alphanumeric characters".to_owned())
+    }
+}
+
+/// Marks the active span as errored and picks a status code for `e`. Only
+/// errors the client can act on have their details passed along.
+///
+/// With `verbose`, the full error chain is sent instead of a generic message,
+/// which is handy in development but leaks internals in production.
+fn error_response(e: &color_eyre::Report, headers: &HeaderMap, verbose: bool) -> Response<BoxBody> {
+    let trace_id = get_active_span(|span| {
+        span.set_status(Status::Error {
+            description: format!("{e}").into(),
+        });
+        span.span_context().trace_id()
+    });
+
+    let (status, message) = status_for_error(e);
+    if status.is_server_error() {
+        // Tagged so the Sentry issue leads to the trace in Honeycomb.
+        sentry::with_scope(
+            |scope| {
+                if trace_id != TraceId::INVALID {
+                    scope.set_tag("trace_id", trace_id);
+                }
+            },
+            || sentry::capture_message(&format!("{e:#}"), sentry::Level::Error),
+        );
+    }
+    let message = if verbose { format!("{e:#}") } else { message };
+    error_body(status, &message, trace_id, headers)
+}
+
+/// Picks the status code and client-facing message for `e`.
+fn status_for_error(e: &color_eyre::Report) -> (StatusCode, String) {
+    match e.downcast_ref::<CatApiError>() {
+        Some(e @ (CatApiError::UnknownBreed(_) | CatApiError::UnknownImage(_))) => {
+            return (StatusCode::NOT_FOUND, e.to_string())
+        }
+        Some(e @ CatApiError::CircuitOpen) => {
+            return (StatusCode::SERVICE_UNAVAILABLE, e.to_string())
+        }
+        Some(e @ CatApiError::RateLimited { .. }) => {
+            return (StatusCode::TOO_MANY_REQUESTS, e.to_string())
+        }
+        Some(e @ CatApiError::NoImages) => return (StatusCode::BAD_GATEWAY, e.to_string()),
+        None => {}
+    }
+    match e.downcast_ref::<DownloadError>() {
+        Some(e @ DownloadError::TooLarge { .. }) => {
+            return (StatusCode::PAYLOAD_TOO_LARGE, e.to_string())
+        }
+        Some(e @ (DownloadError::NotAnImage { .. } | DownloadError::TooSmall { .. })) => {
+            return (StatusCode::BAD_GATEWAY, e.to_string())
+        }
+        None => {}
+    }
+    if let Some(e) = e.downcast_ref::<DecodeError>() {
+        return (StatusCode::UNSUPPORTED_MEDIA_TYPE, e.to_string());
+    }
+    if let Some(e) = e.downcast_ref::<PipelineTimeout>() {
+        return (StatusCode::GATEWAY_TIMEOUT, e.to_string());
+    }
+    // Anything that went wrong talking to someone else is their problem, not
+    // a bug of ours, except for requests we failed to even build.
+    match e.downcast_ref::<reqwest::Error>() {
+        Some(e) if e.is_timeout() => {
+            return (StatusCode::GATEWAY_TIMEOUT, "Upstream timed out".to_owned())
+        }
+        Some(e) if !e.is_builder() => {
+            return (
+                StatusCode::BAD_GATEWAY,
+                "Upstream request failed".to_owned(),
+            )
+        }
+        _ => {}
+    }
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Something went wrong".to_owned(),
+    )
+}
+
+#[derive(Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams))]
+#[cfg_attr(feature = "openapi", into_params(parameter_in = Query))]
+struct CatPngQuery {
+    width: Option<String>,
+}
+
+/// Serves the original cat picture as a PNG, optionally resized.
+#[cfg_attr(feature = "openapi", utoipa::path(
+    get,
+    path = "/cat.png",
+    params(CatPngQuery),
+    responses(
+        (status = 200, description = "A cat picture", content_type = "image/png"),
+        (status = 400, description = "Invalid width"),
+    ),
+))]
+async fn cat_png_get(
+    headers: HeaderMap,
+    Query(query): Query<CatPngQuery>,
+    State(state): State<Arc<ServerState>>,
+) -> Response<BoxBody> {
+    let mut span = start_request_span("cat_png_get", &headers);
+
+    let width = match query.width.as_deref().map(parse_width).transpose() {
+        Ok(width) => width,
+        Err(message) => return bad_request(&mut span, &headers, message),
+    };
+    if let Some(width) = width {
+        span.set_attribute(KeyValue::new("requested_width", width as i64));
+    }
+
+    match get_cat_png(&state, width)
+        .with_context(Context::current_with_span(span))
+        .await
+    {
+        Ok(png) => ([(header::CONTENT_TYPE, "image/png")], png).into_response(),
+        Err(e) => error_response(&e, &headers, state.verbose_errors),
+    }
+}
+
+async fn get_cat_png(state: &ServerState, width: Option<u32>) -> color_eyre::Result<Vec<u8>> {
+    let tracer = global::tracer("");
+    let image_url = state
+        .cat_images
+        .random_image_url(state, None, None)
+        .with_context(Context::current_with_span(
+            tracer.start("get_cat_image_url"),
+        ))
+        .await?;
+
+    let image_bytes = download_file(state, &state.client, &image_url)
+        .with_context(Context::current_with_span(tracer.start("download_file")))
+        .await?;
+
+    // CPU-bound like converting to art, so off the async workers too.
+    let cx = Context::current();
+    tokio::task::spawn_blocking(move || {
+        let _guard = cx.attach();
+        let tracer = global::tracer("");
+        let mut image = decode_image(&image_bytes)?;
+
+        if let Some(width) = width {
+            image = tracer.in_span("image::resize", |_cx| {
+                image.resize(width, u32::MAX, image::imageops::FilterType::Triangle)
+            });
+        }
+
+        tracer.in_span("image::write_to", |_cx| {
+            let mut png = Vec::new();
+            image.write_to(
+                &mut std::io::Cursor::new(&mut png),
+                image::ImageOutputFormat::Png,
+            )?;
+            Ok(png)
+        })
+    })
+    .await
+    .wrap_err("Image encoding task failed")?
+}
+
+/// How much the client wants `media_type` according to its `Accept` header,
+/// from 0 (not at all, or not mentioned) to 1. Wildcards are ignored.
+fn accept_quality(headers: &HeaderMap, media_type: &str) -> f32 {
+    let accept = headers
+        .get(header::ACCEPT)
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or_default();
+
+    accept
+        .split(',')
+        .find_map(|entry| {
+            let mut parts = entry.split(';').map(str::trim);
+            if parts.next() != Some(media_type) {
+                return None;
+            }
+            Some(
+                parts
+                    .find_map(|param| param.strip_prefix("q="))
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .unwrap_or(1.0),
+            )
+        })
+        .unwrap_or(0.0)
+}
+
+/// The flavor of ASCII art sent back to the client.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum ArtFormat {
+    /// Colored art wrapped in HTML, for browsers.
+    Html,
+    /// Uncolored art, for `curl` and friends.
+    PlainText,
+    /// Art colored with ANSI escape codes, for terminals.
+    Ansi,
+    /// Uncolored art plus metadata about the picture, for programs.
+    Json,
+}
+
+impl ArtFormat {
+    /// Picks a format from the `Accept` header. HTML wins unless the client
+    /// prefers `text/plain` over `text/html`.
+    fn from_accept(headers: &HeaderMap) -> Self {
+        if accept_quality(headers, "text/plain") > accept_quality(headers, "text/html") {
+            Self::PlainText
+        } else {
+            Self::Html
+        }
+    }
+
+    /// Parses the `?format=` (or `?target=`) query parameter `name`.
+    fn from_param(name: &str, raw: &str) -> Result<Self, String> {
+        match raw {
+            "html" => Ok(Self::Html),
+            "plain" => Ok(Self::PlainText),
+            "ansi" => Ok(Self::Ansi),
+            "json" => Ok(Self::Json),
+            _ => Err(format!("{name} must be one of html, plain, ansi or json")),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Html => "html",
+            Self::PlainText => "plain",
+            Self::Ansi => "ansi",
+            Self::Json => "json",
+        }
+    }
+
+    fn file_extension(self) -> &'static str {
+        match self {
+            Self::Html => "html",
+            Self::PlainText | Self::Ansi | Self::Json => "txt",
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            Self::Html => "text/html; charset=utf-8",
+            Self::PlainText | Self::Ansi => "text/plain; charset=utf-8",
+            Self::Json => "application/json",
+        }
+    }
+
+    fn target(self, color: bool) -> artem::options::TargetType {
+        match self {
+            Self::Html => artem::options::TargetType::HtmlFile(color, color),
+            Self::PlainText | Self::Json => artem::options::TargetType::Shell(false, false),
+            Self::Ansi => artem::options::TargetType::Shell(color, false),
+        }
+    }
+}
+
+/// Valid range for the `?width=` query parameter, in columns.
+const WIDTH_RANGE: std::ops::RangeInclusive<u32> = 20..=400;
+
+/// Everything the client asked for that affects how the art is rendered.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct ArtOptions {
+    format: ArtFormat,
+    /// Target width in columns, or `None` to let artem decide.
+    width: Option<u32>,
+    /// Whether formats that support color use it.
+    color: bool,
+    /// Flip the brightness ramp, for light backgrounds.
+    invert: bool,
+    /// Density ramp to draw with, or `None` for artem's own.
+    characters: Option<String>,
+    /// Send bare HTML art for embedding, instead of a full page.
+    raw: bool,
+    mode: RenderMode,
+    /// How much to squash the picture vertically before drawing it, in
+    /// percent, to make up for characters being taller than they're wide.
+    aspect_ratio: u32,
+    /// Floyd–Steinberg dither to black and white first. Loses color, but
+    /// brings out detail in flat, low-contrast pictures.
+    dither: bool,
+    /// Reduce the picture to this many colors first, for a flat, poster-like
+    /// look that also makes for smaller HTML.
+    colors: Option<u32>,
+    /// Draw only the outlines, for a line art look.
+    edges: bool,
+    /// Frame the art, for embedding. Only artem draws one.
+    border: bool,
+    /// Colors of the HTML page. Light pages flip the ramp by default too, so
+    /// the art stays dark-on-light.
+    theme: page::Theme,
+}
+
+/// How pixels are turned into characters.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum RenderMode {
+    /// artem's density ramp.
+    Ascii,
+    /// Braille patterns, finer but monochrome.
+    Braille,
+}
+
+impl RenderMode {
+    /// Parses the `?mode=` query parameter.
+    fn from_param(raw: &str) -> Result<Self, String> {
+        match raw {
+            "ascii" => Ok(Self::Ascii),
+            "braille" => Ok(Self::Braille),
+            _ => Err("mode must be ascii or braille".to_owned()),
+        }
+    }
+}
+
+/// Braille art width when the client doesn't pick one, in columns.
+const BRAILLE_DEFAULT_WIDTH: u32 = 80;
+
+/// What artem draws at when the client doesn't pick a width, in columns.
+const ARTEM_DEFAULT_WIDTH: u32 = 80;
+
+/// Ends art that had to be cut short.
+const TRUNCATED_NOTICE: &str = "\n[truncated]";
+
+impl Default for ArtOptions {
+    fn default() -> Self {
+        Self {
+            format: ArtFormat::Html,
+            width: None,
+            color: true,
+            invert: false,
+            characters: None,
+            raw: false,
+            mode: RenderMode::Ascii,
+            aspect_ratio: DEFAULT_ASPECT_RATIO,
+            dither: false,
+            colors: None,
+            edges: false,
+            border: false,
+            theme: page::Theme::Dark,
+        }
+    }
+}
+
+impl ArtOptions {
+    /// Builds options from the request on top of `defaults`, returning a
+    /// user-facing message if any query parameter is invalid.
+    fn from_request(
+        defaults: &Self,
+        headers: &HeaderMap,
+        query: &RootQuery,
+    ) -> Result<Self, String> {
+        let preset_defaults;
+        let defaults = match &query.preset {
+            Some(preset) => {
+                preset_defaults = preset::Preset::from_param(preset)?.apply(defaults);
+                &preset_defaults
+            }
+            None => defaults,
+        };
+        let width = match (&query.width, &query.size) {
+            (Some(_), Some(_)) => return Err("pick either width or size, not both".to_owned()),
+            (Some(width), None) => Some(parse_width(width)?),
+            (None, Some(size)) => Some(size_width(size)?),
+            (None, None) => defaults.width,
+        };
+        let color = parse_bool_param("color", query.color.as_deref())?;
+        let grayscale = parse_bool_param("grayscale", query.grayscale.as_deref())?;
+        let color = match (color, grayscale) {
+            (Some(color), Some(grayscale)) if color == grayscale => {
+                return Err("color and grayscale contradict each other".to_owned())
+            }
+            (Some(color), _) => color,
+            (None, Some(grayscale)) => !grayscale,
+            (None, None) => defaults.color,
+        };
+        let characters = match &query.chars {
+            Some(chars) => Some(validate_characters(chars)?.to_owned()),
+            None => defaults.characters.clone(),
+        };
+
+        let theme = match (&query.theme, headers.get("sec-ch-prefers-color-scheme")) {
+            (Some(theme), _) => page::Theme::from_param(theme)?,
+            // Browsers that don't know the hint ignore it, and the ones
+            // that do deserve the benefit of the doubt on odd values.
+            (None, Some(hint)) => hint
+                .to_str()
+                .ok()
+                .and_then(|hint| page::Theme::from_param(hint.trim_matches('"')).ok())
+                .unwrap_or(defaults.theme),
+            (None, None) => defaults.theme,
+        };
+        let mode = match &query.mode {
+            Some(mode) => RenderMode::from_param(mode)?,
+            None => defaults.mode,
+        };
+        let border = parse_bool_param("border", query.border.as_deref())?;
+        if border == Some(true) && mode == RenderMode::Braille {
+            return Err("border only works in ascii mode".to_owned());
+        }
+        let border = border.unwrap_or(defaults.border);
+
+        let format = match (&query.format, &query.target) {
+            (Some(_), Some(_)) => {
+                return Err("format and target mean the same thing, pick one".to_owned())
+            }
+            (Some(format), None) => ArtFormat::from_param("format", format)?,
+            (None, Some(target)) => ArtFormat::from_param("target", target)?,
+            (None, None) => ArtFormat::from_accept(headers),
+        };
+
+        Ok(Self {
+            format,
+            width,
+            color,
+            invert: parse_bool_param("invert", query.invert.as_deref())?
+                .unwrap_or(defaults.invert != (theme == page::Theme::Light)),
+            characters,
+            raw: parse_bool_param("raw", query.raw.as_deref())?.unwrap_or(defaults.raw),
+            mode,
+            aspect_ratio: match &query.aspect {
+                Some(aspect) => parse_aspect_ratio(aspect)?,
+                None => defaults.aspect_ratio,
+            },
+            dither: parse_bool_param("dither", query.dither.as_deref())?.unwrap_or(defaults.dither),
+            colors: match &query.colors {
+                Some(colors) => Some(parse_colors(colors)?),
+                None => defaults.colors,
+            },
+            edges: parse_bool_param("edges", query.edges.as_deref())?.unwrap_or(defaults.edges),
+            border,
+            theme,
+        })
+    }
+
+    /// Draws `image` as art in its own span, borrowed so `convert_capped` can retry.
+    fn convert(&self, image: &image::DynamicImage, max_chars: usize) -> color_eyre::Result<String> {
+        let tracer = global::tracer("");
+        get_active_span(|span| {
+            span.set_attribute(KeyValue::new(
+                "aspect_ratio",
+                self.aspect_ratio as f64 / 100.0,
+            ))
+        });
+        let image = if self.aspect_ratio == 100 {
+            Cow::Borrowed(image)
+        } else {
+            Cow::Owned(tracer.in_span("image::squash", |_cx| {
+                let height = (image.height() as u64 * self.aspect_ratio as u64 / 100).max(1);
+                image.resize_exact(
+                    image.width(),
+                    height as u32,
+                    image::imageops::FilterType::Triangle,
+                )
+            }))
+        };
+        let image = if self.edges {
+            Cow::Owned(tracer.in_span("imageproc::sobel", |_cx| {
+                let gradients = imageproc::gradients::sobel_gradients(&image.to_luma8());
+                // Gradients go way past 255 on sharp edges, clamp rather
+                // than scale so faint outlines stay visible.
+                let edges =
+                    image::ImageBuffer::from_fn(gradients.width(), gradients.height(), |x, y| {
+                        image::Luma([gradients.get_pixel(x, y).0[0].min(255) as u8])
+                    });
+                image::DynamicImage::ImageLuma8(edges)
+            }))
+        } else {
+            image
+        };
+        let image = if self.dither {
+            Cow::Owned(tracer.in_span("image::dither", |_cx| {
+                let mut luma = image.into_owned().into_luma8();
+                image::imageops::dither(&mut luma, &image::imageops::BiLevel);
+                image::DynamicImage::ImageLuma8(luma)
+            }))
+        } else {
+            image
+        };
+        let image = match self.colors {
+            Some(colors) => Cow::Owned(tracer.in_span("image::quantize", |_cx| {
+                use image::imageops::ColorMap;
+
+                let mut rgba = image.into_owned().into_rgba8();
+                let palette = color_quant::NeuQuant::new(10, colors as usize, rgba.as_raw());
+                // Mapped without dithering, so areas stay flat.
+                for pixel in rgba.pixels_mut() {
+                    palette.map_color(pixel);
+                }
+                image::DynamicImage::ImageRgba8(rgba)
+            })),
+            None => image,
+        };
+
+        match self.mode {
+            RenderMode::Ascii => tracer.in_span("artem::convert", |_cx| {
+                let artem_options = self.artem_options();
+                catch_conversion_panic(|| artem::convert(image.into_owned(), artem_options)).map(
+                    |art| match self.format {
+                        ArtFormat::Html => html_fragment(&art).to_owned(),
+                        _ => art,
+                    },
+                )
+            }),
+            RenderMode::Braille => tracer.in_span("braille::render", |_cx| {
+                let art = braille::render(
+                    &image,
+                    self.width.unwrap_or(BRAILLE_DEFAULT_WIDTH),
+                    self.invert,
+                    max_chars,
+                );
+                Ok(match self.format {
+                    ArtFormat::Html => format!("<pre>{art}</pre>"),
+                    _ => art,
+                })
+            }),
+        }
+    }
+
+    /// Like [`ArtOptions::convert`], but keeps the art within `max_chars`:
+    /// too long art is drawn again narrower, and cut short if that still
+    /// wasn't enough.
+    fn convert_capped(
+        &self,
+        image: &image::DynamicImage,
+        max_chars: usize,
+    ) -> color_eyre::Result<String> {
+        let art = self.convert(image, max_chars)?;
+        let len = art.chars().count();
+        if len <= max_chars {
+            get_active_span(|span| span.set_attribute(KeyValue::new("art.truncated", false)));
+            return Ok(art);
+        }
+
+        let width = self.width.unwrap_or(match self.mode {
+            RenderMode::Ascii => ARTEM_DEFAULT_WIDTH,
+            RenderMode::Braille => BRAILLE_DEFAULT_WIDTH,
+        });
+        // Rows shrink along with columns, so length goes with width squared.
+        let narrower = (width as f64 * (max_chars as f64 / len as f64).sqrt()) as u32;
+        let narrower = narrower.clamp(1, width.saturating_sub(1).max(1));
+        let art = Self {
+            width: Some(narrower),
+            ..self.clone()
+        }
+        .convert(image, max_chars)?;
+
+        let truncated = art.chars().count() > max_chars;
+        get_active_span(|span| {
+            span.set_attribute(KeyValue::new("art.narrowed_width", narrower as i64));
+            span.set_attribute(KeyValue::new("art.truncated", truncated));
+        });
+        Ok(if truncated {
+            truncate_art(&art, max_chars)
+        } else {
+            art
+        })
+    }
+
+    fn artem_options(&self) -> artem::options::Option {
+        let mut builder = artem::options::OptionBuilder::new();
+        builder.target(self.format.target(self.color));
+        builder.invert(self.invert);
+        builder.border(self.border);
+        if let Some(characters) = &self.characters {
+            builder.characters(characters.clone());
+        }
+        if let Some(width) = self.width.and_then(std::num::NonZeroU32::new) {
+            builder.target_size(width);
+        }
+        builder.build()
+    }
+}
+
+/// Runs `convert`, turning a panic into an error on the active span.
+/// Arbitrary images go into artem, so keep a panic in there from taking the
+/// whole blocking task down. Sentry's panic hook still reports it.
+fn catch_conversion_panic(convert: impl FnOnce() -> String) -> color_eyre::Result<String> {
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(convert)).map_err(|panic| {
+        let e = ConversionPanicked(panic_message(panic.as_ref()));
+        get_active_span(|span| {
+            span.set_status(Status::Error {
+                description: e.to_string().into(),
+            })
+        });
+        e.into()
+    })
+}
+
+/// Cuts `art` down to whole lines fitting in `max_chars`, notice included.
+fn truncate_art(art: &str, max_chars: usize) -> String {
+    let budget = max_chars.saturating_sub(TRUNCATED_NOTICE.chars().count());
+    let end = art
+        .char_indices()
+        .nth(budget)
+        .map_or(art.len(), |(index, _)| index);
+    let kept = &art[..end];
+    let kept = kept.rfind('\n').map_or(kept, |newline| &kept[..newline]);
+    format!("{kept}{TRUNCATED_NOTICE}")
+}
+
+/// Valid range for the `?count=` query parameter.
+const COUNT_RANGE: std::ops::RangeInclusive<u32> = 1..=9;
+
+fn parse_count(raw: &str) -> Result<u32, String> {
+    match raw.parse::<u32>() {
+        Ok(count) if COUNT_RANGE.contains(&count) => Ok(count),
+        _ => Err(format!(
+            "count must be an integer between {} and {}",
+            COUNT_RANGE.start(),
+            COUNT_RANGE.end()
+        )),
+    }
+}
+
+/// Parses the optional boolean query parameter `name`.
+fn parse_bool_param(name: &str, raw: Option<&str>) -> Result<Option<bool>, String> {
+    match raw {
+        None => Ok(None),
+        Some("true" | "1") => Ok(Some(true)),
+        Some("false" | "0") => Ok(Some(false)),
+        Some(_) => Err(format!("{name} must be true or false")),
+    }
+}
+
+/// artem already corrects for typical terminal fonts, so by default we
+/// leave the proportions alone.
+const DEFAULT_ASPECT_RATIO: u32 = 100;
+
+/// Valid range for `?aspect=` and `$ASPECT_RATIO`, in percent.
+const ASPECT_RATIO_RANGE: std::ops::RangeInclusive<u32> = 25..=400;
+
+/// Parses an aspect ratio correction like `0.8` into percent.
+fn parse_aspect_ratio(raw: &str) -> Result<u32, String> {
+    let invalid = || {
+        format!(
+            "aspect must be a number between {} and {}",
+            *ASPECT_RATIO_RANGE.start() as f64 / 100.0,
+            *ASPECT_RATIO_RANGE.end() as f64 / 100.0
+        )
+    };
+    let factor = raw.parse::<f64>().map_err(|_| invalid())?;
+    let percent = (factor * 100.0).round();
+    if percent.is_finite() && ASPECT_RATIO_RANGE.contains(&(percent as u32)) {
+        Ok(percent as u32)
+    } else {
+        Err(invalid())
+    }
+}
+
+/// Valid range for the `?colors=` query parameter.
+const COLORS_RANGE: std::ops::RangeInclusive<u32> = 2..=64;
+
+fn parse_colors(raw: &str) -> Result<u32, String> {
+    match raw.parse::<u32>() {
+        Ok(colors) if COLORS_RANGE.contains(&colors) => Ok(colors),
+        _ => Err(format!(
+            "colors must be an integer between {} and {}",
+            COLORS_RANGE.start(),
+            COLORS_RANGE.end()
+        )),
+    }
+}
+
+/// Longest density ramp we accept, in characters.
+const MAX_CHARACTERS: usize = 64;
+
+/// Checks a density ramp, from `?chars=` or `$ASCII_CHARACTERS`.
+fn validate_characters(chars: &str) -> Result<&str, String> {
+    let count = chars.chars().count();
+    if count == 0 || count > MAX_CHARACTERS {
+        return Err(format!(
+            "chars must be between 1 and {MAX_CHARACTERS} characters long"
+        ));
+    }
+    if chars.chars().any(char::is_control) {
+        return Err("chars must not contain control characters".to_owned());
+    }
+    Ok(chars)
+}
+
+fn parse_width(raw: &str) -> Result<u32, String> {
+    validate_width(raw.parse::<u32>().map_err(|_| invalid_width())?)
+}
+
+/// The width behind `?size=`, in columns.
+fn size_width(size: &str) -> Result<u32, String> {
+    match size {
+        "small" => Ok(60),
+        "medium" => Ok(120),
+        "large" => Ok(200),
+        _ => Err("size must be small, medium or large".to_owned()),
+    }
+}
+
+fn validate_width(width: u32) -> Result<u32, String> {
+    if WIDTH_RANGE.contains(&width) {
+        Ok(width)
+    } else {
+        Err(invalid_width())
+    }
+}
+
+fn invalid_width() -> String {
+    format!(
+        "width must be an integer between {} and {}",
+        WIDTH_RANGE.start(),
+        WIDTH_RANGE.end()
+    )
+}
+
+/// A converted picture, along with where it came from.
+#[derive(Clone)]
+struct Art {
+    text: String,
+    /// `None` for the fallback cat.
+    source_url: Option<String>,
+    /// Dimensions of the source picture, in pixels.
+    width: u32,
+    height: u32,
+}
+
+async fn get_ascii_art(
+    state: &ServerState,
+    source: &dyn AnimalSource,
+    options: &ArtOptions,
+) -> color_eyre::Result<Art> {
+    let tracer = global::tracer("");
+    let image_url = source
+        .image_url(state)
+        .with_context(Context::current_with_span(
+            tracer.start(format!("get_{}_image_url", source.name())),
+        ))
+        .await?;
+    breadcrumb(format!("Got image URL {image_url}"));
+
+    let cache_key = (image_url, options.clone());
+    let cached = state.art_cache.get(&cache_key);
+    get_active_span(|span| span.set_attribute(KeyValue::new("cache.hit", cached.is_some())));
+    if let Some(art) = cached {
+        Stats::increment(&state.stats.cache_hits);
+        return Ok(art);
+    }
+    let image_url = &cache_key.0;
+
+    let image_bytes = download_file(state, source.client(state), image_url)
+        .with_context(Context::current_with_span(tracer.start("download_file")))
         .await?;
+    breadcrumb(format!("Downloaded {} bytes", image_bytes.len()));
+
+    // Decoding and converting are CPU-bound, keep them off the async workers
+    // so one big cat doesn't stall every other request.
+    let max_image_dimension = state.max_image_dimension;
+    let max_output_chars = state.max_output_chars;
+    let convert_options = options.clone();
+    let cx = Context::current();
+    // The request's hub, so breadcrumbs land next to the others and show
+    // which step a failing conversion got to.
+    let hub = sentry::Hub::current();
+    let (text, width, height, image_bytes) = tokio::task::spawn_blocking(move || {
+        let _guard = cx.attach();
+        sentry::Hub::run(hub, || {
+            let image = decode_image(&image_bytes)?;
+            let (width, height) = (image.width(), image.height());
+            breadcrumb(format!("Decoded a {width}x{height} image"));
+            let image = downscale(image, max_image_dimension);
+
+            let text = convert_options.convert_capped(&image, max_output_chars)?;
+            breadcrumb(format!("Converted to {} bytes of art", text.len()));
+            Ok::<_, color_eyre::Report>((text, width, height, image_bytes))
+        })
+    })
+    .await
+    .wrap_err("Image conversion task failed")??;
+
+    if let Some(dir) = state.archive_dir.as_ref().filter(|_| source.archived()) {
+        archive::spawn_archive(
+            dir.clone(),
+            image_bytes,
+            text.clone(),
+            options.format.file_extension(),
+        );
+    }
+
+    let art = Art {
+        text,
+        source_url: Some(image_url.clone()),
+        width,
+        height,
+    };
+    state.art_cache.insert(cache_key, art.clone());
+
+    Ok(art)
+}
+
+/// Leaves a trail in Sentry of how far a request got before failing.
+fn breadcrumb(message: String) {
+    sentry::add_breadcrumb(sentry::Breadcrumb {
+        category: Some("pipeline".to_owned()),
+        message: Some(message),
+        ..Default::default()
+    });
+}
+
+/// Renders `count` animals concurrently, each under its own span. Animals
+/// that fail are left out; only if all of them fail is the request failed.
+async fn get_ascii_art_grid(
+    state: &ServerState,
+    source: &dyn AnimalSource,
+    options: &ArtOptions,
+    count: u32,
+) -> color_eyre::Result<Vec<Art>> {
+    let tracer = global::tracer("");
+    let results = futures_util::future::join_all((0..count).map(|index| {
+        let mut span = tracer.start("get_ascii_art");
+        span.set_attribute(KeyValue::new("grid.index", index as i64));
+        async move {
+            let result = get_ascii_art(state, source, options).await;
+            if let Err(e) = &result {
+                get_active_span(|span| {
+                    span.set_status(Status::Error {
+                        description: format!("{e}").into(),
+                    })
+                });
+            }
+            result
+        }
+        .with_context(Context::current_with_span(span))
+    }))
+    .await;
+
+    let mut arts = Vec::new();
+    let mut first_error = None;
+    for result in results {
+        match result {
+            Ok(art) => arts.push(art),
+            Err(e) => {
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+    get_active_span(|span| {
+        span.set_attribute(KeyValue::new(
+            "grid.failed",
+            (count as usize - arts.len()) as i64,
+        ))
+    });
+    match first_error {
+        Some(e) if arts.is_empty() => Err(e),
+        _ => Ok(arts),
+    }
+}
+
+/// Lays several pieces of art out in one body: side by side in HTML, one
+/// under the other in text.
+/// The `<pre>` out of the whole document artem's HTML target makes, so the
+/// art can go in our page, or someone else's with `?raw=true`.
+fn html_fragment(document: &str) -> &str {
+    match (document.find("<pre>"), document.rfind("</pre>")) {
+        (Some(start), Some(end)) if start < end => &document[start..end + "</pre>".len()],
+        _ => document,
+    }
+}
+
+fn join_arts(format: ArtFormat, arts: &[Art]) -> String {
+    match format {
+        ArtFormat::Html if arts.len() > 1 => arts
+            .iter()
+            .map(|art| format!("<div class=\"cat\">{}</div>", art.text))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => arts
+            .iter()
+            .map(|art| art.text.as_str())
+            .collect::<Vec<_>>()
+            .join("\n\n"),
+    }
+}
+
+/// Shrinks `image` so that neither side is over `max` pixels, keeping its
+/// aspect ratio. Huge pictures only make artem slow, the output is no better.
+fn downscale(image: image::DynamicImage, max: u32) -> image::DynamicImage {
+    let tracer = global::tracer("");
+    tracer.in_span("image::downscale", |cx| {
+        let span = cx.span();
+        span.set_attribute(KeyValue::new("original_width", image.width() as i64));
+        span.set_attribute(KeyValue::new("original_height", image.height() as i64));
+
+        let image = if image.width() > max || image.height() > max {
+            image.resize(max, max, image::imageops::FilterType::Triangle)
+        } else {
+            image
+        };
+
+        span.set_attribute(KeyValue::new("resized_width", image.width() as i64));
+        span.set_attribute(KeyValue::new("resized_height", image.height() as i64));
+        image
+    })
+}
 
-    Ok(bytes.to_vec())
+/// Served when upstream is down and `$FALLBACK_ENABLED` is set.
+const FALLBACK_CAT: &[u8] = include_bytes!("../assets/fallback-cat.png");
+
+/// Renders [`FALLBACK_CAT`] without going anywhere near the network.
+fn render_fallback(options: &ArtOptions) -> color_eyre::Result<Art> {
+    let tracer = global::tracer("");
+    tracer.in_span("render_fallback", |_cx| {
+        let image = decode_image(FALLBACK_CAT)?;
+        let (width, height) = (image.width(), image.height());
+        Ok(Art {
+            text: options.convert(&image, usize::MAX)?,
+            source_url: None,
+            width,
+            height,
+        })
+    })
+}
+
+/// Decodes downloaded bytes, taking the first frame of animated GIFs.
+fn decode_image(bytes: &[u8]) -> color_eyre::Result<image::DynamicImage> {
+    let tracer = global::tracer("");
+
+    tracer.in_span("image::load_from_memory", |cx| {
+        let format = image::guess_format(bytes).map_err(|_| DecodeError::UnknownFormat)?;
+        let format_name = format!("{format:?}").to_lowercase();
+        cx.span()
+            .set_attribute(KeyValue::new("format", format_name.clone()));
+        if !format.can_read() {
+            return Err(DecodeError::Unsupported(format_name).into());
+        }
+
+        let img = if format == image::ImageFormat::Gif {
+            use image::AnimationDecoder;
+
+            let mut frames = image::codecs::gif::GifDecoder::new(std::io::Cursor::new(bytes))
+                .wrap_err("Failed to decode GIF frames")?
+                .into_frames();
+            let first = frames
+                .next()
+                .ok_or_else(|| color_eyre::eyre::eyre!("GIF has no frames"))?
+                .wrap_err("Failed to decode GIF frames")?;
+            // The rest are only counted, each dropped as soon as it's decoded.
+            cx.span()
+                .set_attribute(KeyValue::new("frames", 1 + frames.count() as i64));
+            image::DynamicImage::ImageRgba8(first.into_buffer())
+        } else {
+            image::load_from_memory_with_format(bytes, format).map_err(|e| match e {
+                image::ImageError::Unsupported(_) => DecodeError::Unsupported(format_name).into(),
+                e => color_eyre::Report::from(e),
+            })?
+        };
+        cx.span()
+            .set_attribute(KeyValue::new("width", img.width() as i64));
+        cx.span()
+            .set_attribute(KeyValue::new("height", img.height() as i64));
+        Ok(img)
+    })
+}
+
+pub const CAT_API_URL: &str = "https://api.thecatapi.com/v1/images/search";
+
+/// Failures of the Cat API that deserve a more specific answer than a 500.
+#[derive(Debug)]
+enum CatApiError {
+    /// The Cat API had no images for the requested breed.
+    UnknownBreed(String),
+    /// The Cat API has no image with this id.
+    UnknownImage(String),
+    /// The Cat API has been failing, so we didn't even try.
+    CircuitOpen,
+    /// We've used up our Cat API quota for now.
+    RateLimited { retry_after: Duration },
+    /// The Cat API answered a random search with no images at all.
+    NoImages,
+}
+
+impl std::fmt::Display for CatApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownBreed(breed) => write!(f, "No cats found for breed {breed:?}"),
+            Self::UnknownImage(id) => write!(f, "No cat with id {id:?}"),
+            Self::CircuitOpen => write!(f, "The Cat API is unavailable, try again later"),
+            Self::RateLimited { retry_after } => write!(
+                f,
+                "Out of cats for now, try again in {} seconds",
+                retry_after.as_secs_f64().ceil()
+            ),
+            Self::NoImages => write!(f, "The Cat API didn't send any cats, try again"),
+        }
+    }
+}
+
+impl std::error::Error for CatApiError {}
+
+#[derive(Debug)]
+enum DownloadError {
+    /// The image is bigger than we're willing to hold in memory.
+    TooLarge { limit: usize },
+    /// The server sent something else, like an HTML error page.
+    NotAnImage { content_type: String },
+    /// Too few bytes to hold an image, usually an empty body.
+    TooSmall { len: usize },
+}
+
+impl std::fmt::Display for DownloadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TooLarge { limit } => write!(f, "Image is larger than {limit} bytes"),
+            Self::NotAnImage { content_type } => {
+                write!(f, "Expected an image, got {content_type:?}")
+            }
+            Self::TooSmall { len: 0 } => write!(f, "Image download was empty"),
+            Self::TooSmall { len } => write!(f, "Image download was only {len} bytes"),
+        }
+    }
+}
+
+impl std::error::Error for DownloadError {}
+
+#[derive(Debug)]
+enum DecodeError {
+    /// The bytes don't look like any image format we know of.
+    UnknownFormat,
+    /// We know the format, but can't decode it (or this flavor of it).
+    Unsupported(String),
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownFormat => write!(f, "Image format not recognized"),
+            Self::Unsupported(format) => write!(f, "Image format {format} is not supported"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Making art took longer than `$PIPELINE_TIMEOUT_SECS`.
+#[derive(Debug)]
+struct PipelineTimeout {
+    budget: Duration,
+}
+
+impl std::fmt::Display for PipelineTimeout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Couldn't make art within {:?}", self.budget)
+    }
+}
+
+impl std::error::Error for PipelineTimeout {}
+
+/// artem panicked on an image, with this message.
+#[derive(Debug)]
+struct ConversionPanicked(String);
+
+impl std::fmt::Display for ConversionPanicked {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Art conversion panicked: {}", self.0)
+    }
+}
+
+impl std::error::Error for ConversionPanicked {}
+
+/// Builds the search URL off `base`, restricted to `breed` if given. `seed`
+/// is passed along for repeatable picks.
+fn cat_api_url(base: &reqwest::Url, breed: Option<&str>, seed: Option<&str>) -> reqwest::Url {
+    let mut url = base.clone();
+    if let Some(breed) = breed {
+        url.query_pairs_mut().append_pair("breed_ids", breed);
+    }
+    if let Some(seed) = seed {
+        url.query_pairs_mut().append_pair("seed", seed);
+    }
+    url
+}
+
+#[derive(Deserialize)]
+struct CatImage {
+    url: String,
+}
+
+async fn get_cat_image_url(
+    state: &ServerState,
+    breed: Option<&str>,
+    seed: Option<&str>,
+) -> color_eyre::Result<String> {
+    check_cat_api_available(state)?;
+
+    let api_url = cat_api_url(&state.cat_api_url, breed, seed);
+    let start = Instant::now();
+    let images = async {
+        let response = send_to_cat_api(state, api_url).await?;
+
+        async {
+            let images = response.json::<Vec<CatImage>>().await;
+            get_active_span(|span| match &images {
+                Ok(images) => span.set_attribute(KeyValue::new("images", images.len() as i64)),
+                Err(e) => span.set_status(Status::Error {
+                    description: format!("Malformed Cat API response: {e}").into(),
+                }),
+            });
+            images
+        }
+        .with_context(Context::current_with_span(
+            global::tracer("").start("parse_cat_api_response"),
+        ))
+        .await
+    }
+    .await;
+    record_upstream_latency("catscii_cat_api_duration_seconds", start, images.is_ok());
+    state.cat_api_breaker.record(images.is_ok());
+    let image = images?.pop();
+
+    match (image, breed) {
+        (Some(image), _) => Ok(image.url),
+        (None, Some(breed)) => Err(CatApiError::UnknownBreed(breed.to_owned()).into()),
+        (None, None) => {
+            get_active_span(|span| span.add_event("cat_api_empty", vec![]));
+            Err(CatApiError::NoImages.into())
+        }
+    }
+}
+
+/// Looks up the image with the given id, as in `/v1/images/{id}`.
+async fn get_cat_image_url_by_id(state: &ServerState, id: &str) -> color_eyre::Result<String> {
+    check_cat_api_available(state)?;
+
+    // Swaps the `search` at the end of the search URL for the id.
+    let api_url = state.cat_api_url.join(id)?;
+    let start = Instant::now();
+    let image = async {
+        send_to_cat_api(state, api_url)
+            .await?
+            .json::<CatImage>()
+            .await
+    }
+    .await;
+    record_upstream_latency("catscii_cat_api_duration_seconds", start, image.is_ok());
+
+    // Unknown ids are the client's mistake, not a sign of an outage.
+    let unknown = matches!(
+        &image,
+        Err(e) if matches!(e.status(), Some(StatusCode::NOT_FOUND | StatusCode::BAD_REQUEST))
+    );
+    state.cat_api_breaker.record(image.is_ok() || unknown);
+    if unknown {
+        return Err(CatApiError::UnknownImage(id.to_owned()).into());
+    }
+    Ok(image?.url)
+}
+
+/// Fails fast if we know better than to call the Cat API right now.
+fn check_cat_api_available(state: &ServerState) -> color_eyre::Result<()> {
+    if let Some(reset) = *state.cat_api_quota_reset.lock().unwrap() {
+        let now = Instant::now();
+        if reset > now {
+            return Err(CatApiError::RateLimited {
+                retry_after: reset - now,
+            }
+            .into());
+        }
+    }
+    if !state.cat_api_breaker.allow() {
+        return Err(CatApiError::CircuitOpen.into());
+    }
+    Ok(())
+}
+
+/// Calls the Cat API with our key, over plain HTTP if HTTPS won't connect
+/// and that's allowed.
+async fn send_to_cat_api(
+    state: &ServerState,
+    api_url: reqwest::Url,
+) -> reqwest::Result<reqwest::Response> {
+    let send = |url: reqwest::Url, with_key: bool| {
+        send_with_retries(&state.retry, move || {
+            let request = state.client.get(url.clone());
+            match &state.cat_api_key {
+                Some(key) if with_key => request.header("x-api-key", key.clone()),
+                _ => request,
+            }
+        })
+    };
+
+    let response = match send(api_url.clone(), true).await {
+        Err(e) if e.is_connect() && state.cat_api_allow_http && api_url.scheme() == "https" => {
+            get_active_span(|span| {
+                span.add_event("http_fallback", vec![KeyValue::new("error", e.to_string())])
+            });
+            let mut http_url = api_url;
+            http_url
+                .set_scheme("http")
+                .expect("https URLs can be switched to http");
+            // The key stays home: it would be readable by anyone on the path.
+            send(http_url, false).await?
+        }
+        result => result?,
+    };
+    record_cat_api_quota(state, response.headers());
+    *state.cat_api_last_success.lock().unwrap() = Some(SystemTime::now());
+    Ok(response)
+}
+
+/// How long to hold off when the Cat API says we're out of requests but not
+/// for how long.
+const DEFAULT_QUOTA_RESET: Duration = Duration::from_secs(60);
+
+/// Longest we hold off for, whatever the Cat API says, so that one bogus
+/// header can't take us down for days.
+const MAX_QUOTA_RESET: Duration = Duration::from_secs(60 * 60);
+
+/// How long until the quota resets, from an `x-ratelimit-reset` that is
+/// either seconds from now or, when it's around the current time, a Unix
+/// timestamp.
+fn quota_reset_delay(reset: u64, now: SystemTime) -> Duration {
+    let now_secs = now
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |since_epoch| since_epoch.as_secs());
+    // A timestamp that just passed means the quota is already back.
+    let delay = if reset > now_secs.saturating_sub(MAX_QUOTA_RESET.as_secs()) {
+        reset.saturating_sub(now_secs)
+    } else {
+        reset
+    };
+    Duration::from_secs(delay).min(MAX_QUOTA_RESET)
+}
+
+/// Reads the Cat API's `x-ratelimit-*` headers, so that once the quota is
+/// used up we stop calling until it resets instead of collecting errors.
+fn record_cat_api_quota(state: &ServerState, headers: &HeaderMap) {
+    let number = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|h| h.to_str().ok())
+            .and_then(|h| h.trim().parse::<u64>().ok())
+    };
+    let remaining = number("x-ratelimit-remaining");
+    let reset =
+        number("x-ratelimit-reset").map(|reset| quota_reset_delay(reset, SystemTime::now()));
+
+    get_active_span(|span| {
+        if let Some(remaining) = remaining {
+            span.set_attribute(KeyValue::new("ratelimit.remaining", remaining as i64));
+        }
+        if let Some(reset) = reset {
+            span.set_attribute(KeyValue::new(
+                "ratelimit.reset_secs",
+                reset.as_secs() as i64,
+            ));
+        }
+    });
+
+    *state.cat_api_quota_reset.lock().unwrap() = match remaining {
+        Some(0) => Some(Instant::now() + reset.unwrap_or(DEFAULT_QUOTA_RESET)),
+        _ => None,
+    };
+}
+
+/// Anything shorter can't be a real picture, not even a 1x1 GIF.
+const MIN_IMAGE_BYTES: usize = 26;
+
+/// Bytes between `download_progress` span events.
+const DOWNLOAD_PROGRESS_INTERVAL: usize = 256 * 1024;
+
+async fn download_file(
+    state: &ServerState,
+    client: &reqwest::Client,
+    url: &str,
+) -> color_eyre::Result<Vec<u8>> {
+    let url = url.to_owned();
+    let cached = state.download_cache.get(&url);
+    get_active_span(|span| {
+        span.set_attribute(KeyValue::new("download.cache_hit", cached.is_some()))
+    });
+    if let Some(bytes) = cached {
+        return Ok(bytes);
+    }
+
+    let start = Instant::now();
+    let mut bytes = fetch_image(state, client, &url).await;
+    // Connections sometimes drop mid-JPEG without an error, and trying again
+    // usually gets the whole thing.
+    let refetched = matches!(&bytes, Ok(bytes) if looks_truncated(bytes));
+    if refetched {
+        bytes = fetch_image(state, client, &url).await;
+    }
+    record_upstream_latency("catscii_download_duration_seconds", start, bytes.is_ok());
+    get_active_span(|span| span.set_attribute(KeyValue::new("download.refetched", refetched)));
+    let bytes = bytes?;
+
+    // Still cut short, let the decoder make what it can of it, but don't
+    // keep it around.
+    if !looks_truncated(&bytes) {
+        state.download_cache.insert(url, bytes.clone());
+    }
+    Ok(bytes)
+}
+
+/// How far from the end a JPEG's end-of-image marker may be, since some
+/// encoders pad after it.
+const JPEG_TRAILER_SLACK: usize = 32;
+
+/// Whether `bytes` are a JPEG missing its end-of-image marker. Other formats
+/// are left to the decoder.
+fn looks_truncated(bytes: &[u8]) -> bool {
+    if !bytes.starts_with(&[0xFF, 0xD8]) {
+        return false;
+    }
+    let tail = &bytes[bytes.len().saturating_sub(JPEG_TRAILER_SLACK)..];
+    !tail.windows(2).any(|marker| marker == [0xFF, 0xD9])
+}
+
+/// Records how long a call to someone else took, labeled by whether it
+/// worked, to tell slow upstreams apart from slow conversions.
+fn record_upstream_latency(name: &'static str, start: Instant, ok: bool) {
+    let outcome = if ok { "ok" } else { "error" };
+    metrics::histogram!(name, start.elapsed().as_secs_f64(), "outcome" => outcome);
+}
+
+/// The uncached part of [`download_file`].
+async fn fetch_image(
+    state: &ServerState,
+    client: &reqwest::Client,
+    url: &str,
+) -> color_eyre::Result<Vec<u8>> {
+    let limit = state.max_download_bytes;
+    let too_large = || DownloadError::TooLarge { limit };
+
+    let response = send_with_retries(&state.retry, || client.get(url)).await?;
+    // Without a content type, leave it to the decoder to make sense of it.
+    if let Some(content_type) = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .map(|h| String::from_utf8_lossy(h.as_bytes()).into_owned())
+    {
+        get_active_span(|span| {
+            span.set_attribute(KeyValue::new("content_type", content_type.clone()))
+        });
+        if !content_type.starts_with("image/") {
+            return Err(DownloadError::NotAnImage { content_type }.into());
+        }
+    }
+    // Cheap early exit, but servers can lie or omit it, so the streaming
+    // loop below enforces the limit too.
+    let content_length = response.content_length();
+    if content_length.is_some_and(|len| len > limit as u64) {
+        return Err(too_large().into());
+    }
+
+    let mut bytes = Vec::with_capacity(content_length.unwrap_or(0) as usize);
+    let mut next_progress_event = DOWNLOAD_PROGRESS_INTERVAL;
+    let mut stream = response.bytes_stream();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk?;
+        if bytes.len() + chunk.len() > limit {
+            return Err(too_large().into());
+        }
+        bytes.extend_from_slice(&chunk);
+
+        if bytes.len() >= next_progress_event {
+            get_active_span(|span| {
+                span.add_event(
+                    "download_progress",
+                    vec![KeyValue::new("bytes", bytes.len() as i64)],
+                )
+            });
+            next_progress_event = bytes.len() + DOWNLOAD_PROGRESS_INTERVAL;
+        }
+    }
+    get_active_span(|span| span.set_attribute(KeyValue::new("bytes", bytes.len() as i64)));
+    if bytes.len() < MIN_IMAGE_BYTES {
+        let e = DownloadError::TooSmall { len: bytes.len() };
+        get_active_span(|span| {
+            span.set_status(Status::Error {
+                description: e.to_string().into(),
+            })
+        });
+        return Err(e.into());
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use axum::extract::ConnectInfo;
+    use std::{
+        collections::HashMap,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    /// A config from `vars` alone, whatever the environment says.
+    fn config(vars: &[(&str, &str)]) -> Config {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        Config::from_vars(|name| vars.get(name).cloned()).unwrap()
+    }
+
+    /// Serves `router` on a free local port.
+    fn serve(router: Router) -> SocketAddr {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(router.into_make_service_with_connect_info::<SocketAddr>());
+        tokio::spawn(server);
+        addr
+    }
+
+    /// The whole app in front of `state`, served.
+    fn serve_app(config: &Config, state: ServerState) -> SocketAddr {
+        serve(app(config, Arc::new(state), Arc::new(|_| Ok(()))))
+    }
+
+    /// A small gradient, enough for artem to draw something.
+    fn png() -> Vec<u8> {
+        let image =
+            image::RgbImage::from_fn(16, 16, |x, y| image::Rgb([x as u8 * 16, y as u8 * 16, 128]));
+        let mut png = Vec::new();
+        image::DynamicImage::ImageRgb8(image)
+            .write_to(
+                &mut std::io::Cursor::new(&mut png),
+                image::ImageOutputFormat::Png,
+            )
+            .unwrap();
+        png
+    }
+
+    /// Serves [`png`] at `/cat.png`, like the Cat API's CDN.
+    fn serve_png() -> SocketAddr {
+        serve(Router::new().route(
+            "/cat.png",
+            get(|| async { ([(header::CONTENT_TYPE, "image/png")], png()) }),
+        ))
+    }
+
+    /// Cats without The Cat API: every cat is the same picture, and the only
+    /// id there is is `known`.
+    struct FakeCats {
+        url: String,
+    }
+
+    #[async_trait]
+    impl CatImageProvider for FakeCats {
+        async fn random_image_url(
+            &self,
+            _state: &ServerState,
+            _breed: Option<&str>,
+            _seed: Option<&str>,
+        ) -> color_eyre::Result<String> {
+            Ok(self.url.clone())
+        }
+
+        async fn image_url_by_id(
+            &self,
+            _state: &ServerState,
+            id: &str,
+        ) -> color_eyre::Result<String> {
+            match id {
+                "known" => Ok(self.url.clone()),
+                _ => Err(CatApiError::UnknownImage(id.to_owned()).into()),
+            }
+        }
+    }
+
+    /// State getting its cats from [`FakeCats`].
+    fn fake_cats(config: &Config) -> ServerState {
+        let mut state = ServerState::new(config);
+        state.cat_images = Box::new(FakeCats {
+            url: format!("http://{}/cat.png", serve_png()),
+        });
+        state
+    }
+
+    /// The app serving cats from [`FakeCats`], configured by `vars`.
+    fn serve_fake_cats(vars: &[(&str, &str)]) -> SocketAddr {
+        let config = config(vars);
+        serve_app(&config, fake_cats(&config))
+    }
+
+    /// Like [`serve_fake_cats`], but every cat is at `url`.
+    fn serve_cats_at(url: String, vars: &[(&str, &str)]) -> SocketAddr {
+        let config = config(vars);
+        let mut state = ServerState::new(&config);
+        state.cat_images = Box::new(FakeCats { url });
+        serve_app(&config, state)
+    }
+
+    /// Serves [`png`] at `/cat.png` in `chunk`-byte pieces, so without a
+    /// `Content-Length`.
+    fn serve_chunked_png(chunk: usize) -> SocketAddr {
+        serve(Router::new().route(
+            "/cat.png",
+            get(move || async move {
+                let chunks: Vec<_> = png()
+                    .chunks(chunk)
+                    .map(|chunk| Ok::<_, std::io::Error>(chunk.to_vec()))
+                    .collect();
+                (
+                    [(header::CONTENT_TYPE, "image/png")],
+                    axum::body::StreamBody::new(futures_util::stream::iter(chunks)),
+                )
+            }),
+        ))
+    }
+
+    /// Cats that take longer to find than any test is willing to wait.
+    struct SlowCats;
+
+    #[async_trait]
+    impl CatImageProvider for SlowCats {
+        async fn random_image_url(
+            &self,
+            _state: &ServerState,
+            _breed: Option<&str>,
+            _seed: Option<&str>,
+        ) -> color_eyre::Result<String> {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            unreachable!("the test should have given up by now")
+        }
+
+        async fn image_url_by_id(
+            &self,
+            state: &ServerState,
+            _id: &str,
+        ) -> color_eyre::Result<String> {
+            self.random_image_url(state, None, None).await
+        }
+    }
+
+    /// A Cat API that knows one breed, `beng`, and one image, `known`, all
+    /// of them [`png`]. Only answers requests with the key `secret`.
+    fn serve_cat_api() -> SocketAddr {
+        #[derive(Deserialize)]
+        struct Search {
+            breed_ids: Option<String>,
+        }
+
+        let image_url = format!("http://{}/cat.png", serve_png());
+        let authorized =
+            |headers: &HeaderMap| headers.get("x-api-key").is_some_and(|key| key == "secret");
+        let search = {
+            let image_url = image_url.clone();
+            move |headers: HeaderMap, Query(search): Query<Search>| async move {
+                if !authorized(&headers) {
+                    return StatusCode::UNAUTHORIZED.into_response();
+                }
+                match search.breed_ids.as_deref() {
+                    None | Some("beng") => {
+                        Json(serde_json::json!([{ "url": image_url }])).into_response()
+                    }
+                    Some(_) => Json(serde_json::json!([])).into_response(),
+                }
+            }
+        };
+        let by_id = move |headers: HeaderMap, Path(id): Path<String>| async move {
+            if !authorized(&headers) {
+                return StatusCode::UNAUTHORIZED.into_response();
+            }
+            match id.as_str() {
+                "known" => Json(serde_json::json!({ "url": image_url })).into_response(),
+                _ => StatusCode::NOT_FOUND.into_response(),
+            }
+        };
+        serve(
+            Router::new()
+                .route("/v1/images/search", get(search))
+                .route("/v1/images/:id", get(by_id)),
+        )
+    }
+
+    /// The app in front of the Cat API at `cat_api`, with `vars` on top.
+    fn serve_with_cat_api(cat_api: SocketAddr, vars: &[(&str, &str)]) -> SocketAddr {
+        let base_url = format!("http://{cat_api}/v1/images/search");
+        let mut vars = vars.to_vec();
+        vars.extend([
+            ("CAT_API_BASE_URL", base_url.as_str()),
+            ("UPSTREAM_MAX_RETRIES", "0"),
+        ]);
+        let config = config(&vars);
+        serve_app(&config, ServerState::new(&config))
+    }
+
+    /// A dog.ceo lookalike whose only dog is [`png`].
+    fn serve_dog_api() -> SocketAddr {
+        let image_url = format!("http://{}/cat.png", serve_png());
+        serve(Router::new().route(
+            "/api/breeds/image/random",
+            get(move || async move { Json(serde_json::json!({ "message": image_url })) }),
+        ))
+    }
+
+    #[tokio::test]
+    async fn cat_source_asks_the_cat_api() {
+        let cat_api = serve_cat_api();
+        let base_url = format!("http://{cat_api}/v1/images/search");
+        let config = config(&[("CAT_API_BASE_URL", &base_url), ("CAT_API_KEY", "secret")]);
+        let state = ServerState::new(&config);
+
+        let url = CatSource::default().image_url(&state).await.unwrap();
+        assert!(url.ends_with("/cat.png"), "{url}");
+        let bengal = CatSource {
+            breed: Some("beng".to_owned()),
+            ..Default::default()
+        };
+        assert_eq!(bengal.image_url(&state).await.unwrap(), url);
+        let unknown = CatSource {
+            breed: Some("abys".to_owned()),
+            ..Default::default()
+        };
+        assert!(unknown.image_url(&state).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn dog_source_asks_the_dog_api() {
+        let dog_api = serve_dog_api();
+        let dog_api_url = format!("http://{dog_api}/api/breeds/image/random");
+        let config = config(&[("DOG_API_URL", &dog_api_url)]);
+        let state = ServerState::new(&config);
+
+        let url = DogSource.image_url(&state).await.unwrap();
+        assert!(url.ends_with("/cat.png"), "{url}");
+        let addr = serve_app(&config, state);
+        assert_eq!(get_status(addr, "/dog").await, StatusCode::OK);
+    }
+
+    async fn get_status(addr: SocketAddr, path: &str) -> StatusCode {
+        reqwest::Client::new()
+            .get(format!("http://{addr}{path}"))
+            .header(header::ACCEPT, "text/plain")
+            .send()
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn serves_cats_from_the_cat_api() {
+        let addr = serve_with_cat_api(serve_cat_api(), &[("CAT_API_KEY", "secret")]);
+        assert_eq!(get_status(addr, "/").await, StatusCode::OK);
+        assert_eq!(get_status(addr, "/?breed=beng").await, StatusCode::OK);
+        assert_eq!(get_status(addr, "/cat/known").await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn unknown_breeds_and_ids_are_not_found() {
+        let addr = serve_with_cat_api(serve_cat_api(), &[("CAT_API_KEY", "secret")]);
+        assert_eq!(
+            get_status(addr, "/?breed=abys").await,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            get_status(addr, "/cat/unknown").await,
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[tokio::test]
+    async fn cat_api_refusing_us_is_a_bad_gateway() {
+        let addr = serve_with_cat_api(serve_cat_api(), &[]);
+        assert_eq!(get_status(addr, "/").await, StatusCode::BAD_GATEWAY);
+    }
+
+    #[tokio::test]
+    async fn cat_api_errors_are_a_bad_gateway() {
+        let broken = serve(Router::new().fallback(|| async { StatusCode::INTERNAL_SERVER_ERROR }));
+        let addr = serve_with_cat_api(broken, &[]);
+        assert_eq!(get_status(addr, "/").await, StatusCode::BAD_GATEWAY);
+        assert_eq!(get_status(addr, "/random").await, StatusCode::BAD_GATEWAY);
+    }
+
+    /// Redirects `/to?url=` wherever `url` says.
+    fn serve_redirects() -> SocketAddr {
+        #[derive(Deserialize)]
+        struct To {
+            url: String,
+        }
+
+        serve(
+            Router::new().route(
+                "/to",
+                get(|Query(to): Query<To>| async move {
+                    (StatusCode::FOUND, [(header::LOCATION, to.url)])
+                }),
+            ),
+        )
+    }
+
+    #[tokio::test]
+    async fn convert_checks_redirects_against_allowed_hosts() {
+        let image = serve_png();
+        let redirects = serve_redirects();
+        let config = config(&[("CONVERT_ALLOWED_HOSTS", "127.0.0.1")]);
+        let addr = serve_app(&config, ServerState::new(&config));
+        let convert = |url: String| {
+            let mut convert_url = reqwest::Url::parse(&format!("http://{addr}/convert")).unwrap();
+            convert_url.query_pairs_mut().append_pair("url", &url);
+            convert_url
+        };
+        let client = reqwest::Client::new();
+        let status = |url: String| {
+            let request = client
+                .get(convert(url))
+                .header(header::ACCEPT, "text/plain");
+            async move { request.send().await.unwrap().status() }
+        };
+
+        let allowed = format!("http://127.0.0.1:{}/cat.png", image.port());
+        let elsewhere = format!("http://localhost:{}/cat.png", image.port());
+        assert_eq!(status(allowed.clone()).await, StatusCode::OK);
+        assert_eq!(status(elsewhere.clone()).await, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            status(format!("http://{redirects}/to?url={allowed}")).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(format!("http://{redirects}/to?url={elsewhere}")).await,
+            StatusCode::BAD_GATEWAY
+        );
+    }
+
+    #[tokio::test]
+    async fn http_fallback_leaves_the_key_out() {
+        let image_url = format!("http://{}/cat.png", serve_png());
+        // Plain HTTP only, so HTTPS fails to connect.
+        let cat_api = serve(Router::new().route(
+            "/v1/images/search",
+            get(move |headers: HeaderMap| async move {
+                match headers.get("x-api-key") {
+                    Some(_) => StatusCode::BAD_REQUEST.into_response(),
+                    None => Json(serde_json::json!([{ "url": image_url }])).into_response(),
+                }
+            }),
+        ));
+        let base_url = format!("https://{cat_api}/v1/images/search");
+        let config = config(&[
+            ("CAT_API_BASE_URL", &base_url),
+            ("CAT_API_ALLOW_HTTP", "true"),
+            ("CAT_API_KEY", "secret"),
+            ("UPSTREAM_MAX_RETRIES", "0"),
+        ]);
+        let addr = serve_app(&config, ServerState::new(&config));
+        assert_eq!(get_status(addr, "/").await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn panics_are_answered_in_the_requested_format() {
+        let config = config(&[]);
+        let addr = serve_app(&config, ServerState::new(&config));
+        let response = reqwest::Client::new()
+            .get(format!("http://{addr}/panic"))
+            .header(header::ACCEPT, "application/json")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["error"], "Something went wrong");
+    }
+
+    #[tokio::test]
+    async fn log_level_needs_credentials_to_exist() {
+        let client = reqwest::Client::new();
+        let open = config(&[]);
+        let addr = serve_app(&open, ServerState::new(&open));
+        let response = client
+            .put(format!("http://{addr}/log-level"))
+            .body("debug")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let guarded = config(&[("BASIC_AUTH_USER", "admin"), ("BASIC_AUTH_PASS", "hunter2")]);
+        let addr = serve_app(&guarded, ServerState::new(&guarded));
+        let response = client
+            .put(format!("http://{addr}/log-level"))
+            .body("debug")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn unauthorized_is_an_error_body() {
+        let config = config(&[("BASIC_AUTH_USER", "admin"), ("BASIC_AUTH_PASS", "hunter2")]);
+        let addr = serve_app(&config, ServerState::new(&config));
+        let response = reqwest::Client::new()
+            .get(format!("http://{addr}/metrics"))
+            .header(header::ACCEPT, "application/json")
+            .header(header::ACCEPT_LANGUAGE, "fr")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(response.headers().contains_key(header::WWW_AUTHENTICATE));
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["error"], "Non autorisé");
+    }
+
+    #[tokio::test]
+    async fn method_not_allowed_lists_the_allowed_methods() {
+        let config = config(&[]);
+        let addr = serve_app(&config, ServerState::new(&config));
+        let response = reqwest::Client::new()
+            .delete(format!("http://{addr}/health"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        let allow = response.headers()[header::ALLOW]
+            .to_str()
+            .unwrap()
+            .to_owned();
+        assert!(allow.contains("GET"), "{allow}");
+        let body = response.text().await.unwrap();
+        assert_eq!(body, format!("Method not allowed (Allow: {allow})"));
+    }
+
+    #[tokio::test]
+    async fn serves_art_of_the_providers_cats() {
+        let addr = serve_fake_cats(&[]);
+        let response = reqwest::Client::new()
+            .get(format!("http://{addr}/"))
+            .header(header::ACCEPT, "text/plain")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(!response.text().await.unwrap().trim().is_empty());
+    }
+
+    #[tokio::test]
+    async fn looks_cats_up_by_id_through_the_provider() {
+        let addr = serve_fake_cats(&[]);
+        let client = reqwest::Client::new();
+        let known = client
+            .get(format!("http://{addr}/cat/known"))
+            .header(header::ACCEPT, "text/plain")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(known.status(), StatusCode::OK);
+
+        let unknown = client
+            .get(format!("http://{addr}/cat/unknown"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(unknown.status(), StatusCode::NOT_FOUND);
+    }
+
+    /// `path` on the app at `addr` as plain text.
+    async fn get_text(addr: SocketAddr, path: &str) -> (StatusCode, String) {
+        let response = reqwest::Client::new()
+            .get(format!("http://{addr}{path}"))
+            .header(header::ACCEPT, "text/plain")
+            .send()
+            .await
+            .unwrap();
+        (response.status(), response.text().await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn width_is_in_columns_and_validated() {
+        let addr = serve_fake_cats(&[]);
+        let (status, _) = get_text(addr, "/").await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, art) = get_text(addr, "/?width=40").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(!art.trim().is_empty());
+        assert!(art.lines().all(|line| line.chars().count() <= 40), "{art}");
+
+        for width in ["0", "19", "401", "wide"] {
+            let (status, body) = get_text(addr, &format!("/?width={width}")).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{width}");
+            assert_eq!(body, "width must be an integer between 20 and 400");
+        }
+    }
+
+    #[tokio::test]
+    async fn shutting_down_lets_requests_finish() {
+        let slow_png = serve(Router::new().route(
+            "/cat.png",
+            get(|| async {
+                tokio::time::sleep(Duration::from_millis(300)).await;
+                ([(header::CONTENT_TYPE, "image/png")], png())
+            }),
+        ));
+        let config = config(&[]);
+        let mut state = ServerState::new(&config);
+        state.cat_images = Box::new(FakeCats {
+            url: format!("http://{slow_png}/cat.png"),
+        });
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(
+                    app(&config, Arc::new(state), Arc::new(|_| Ok(())))
+                        .into_make_service_with_connect_info::<SocketAddr>(),
+                )
+                .with_graceful_shutdown(async {
+                    _ = stopped.await;
+                }),
+        );
+
+        let request = tokio::spawn(get_status(addr, "/"));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        stop.send(()).unwrap();
+        assert_eq!(request.await.unwrap(), StatusCode::OK);
+        tokio::time::timeout(Duration::from_secs(5), server)
+            .await
+            .expect("the server should stop once the request is done")
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn health_is_a_tiny_json_body() {
+        let config = config(&[]);
+        let addr = serve_app(&config, ServerState::new(&config));
+        let response = reqwest::get(format!("http://{addr}/health")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+        assert_eq!(response.text().await.unwrap(), r#"{"status":"ok"}"#);
+    }
+
+    #[tokio::test]
+    async fn ready_only_when_the_cat_api_answers() {
+        let addr = serve_with_cat_api(serve_cat_api(), &[]);
+        assert_eq!(get_status(addr, "/ready").await, StatusCode::OK);
+
+        let failing = serve(Router::new().route(
+            "/v1/images/search",
+            get(|| async { StatusCode::INTERNAL_SERVER_ERROR }),
+        ));
+        let addr = serve_with_cat_api(failing, &[]);
+        assert_eq!(
+            get_status(addr, "/ready").await,
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+
+        // Nothing listens on a port we've let go of.
+        let unreachable = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let addr = serve_with_cat_api(unreachable, &[]);
+        assert_eq!(
+            get_status(addr, "/ready").await,
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+    }
+
+    #[tokio::test]
+    async fn metrics_count_and_time_requests() {
+        let addr = serve_fake_cats(&[]);
+        assert_eq!(get_status(addr, "/").await, StatusCode::OK);
+        let (status, metrics) = get_text(addr, "/metrics").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(
+            metrics.contains(r#"catscii_requests_total{outcome="success"}"#),
+            "{metrics}"
+        );
+        assert!(
+            metrics.contains("catscii_request_duration_seconds_bucket"),
+            "{metrics}"
+        );
+    }
+
+    #[tokio::test]
+    async fn sends_the_api_key_only_when_set() {
+        let image_url = format!("http://{}/cat.png", serve_png());
+        let keys = Arc::new(Mutex::new(Vec::new()));
+        let cat_api = serve(Router::new().route(
+            "/v1/images/search",
+            get({
+                let keys = keys.clone();
+                move |headers: HeaderMap| async move {
+                    keys.lock().unwrap().push(
+                        headers
+                            .get("x-api-key")
+                            .map(|key| key.to_str().unwrap().to_owned()),
+                    );
+                    Json(serde_json::json!([{ "url": image_url }]))
+                }
+            }),
+        ));
+        let keyed = serve_with_cat_api(cat_api, &[("CAT_API_KEY", "secret")]);
+        assert_eq!(get_status(keyed, "/").await, StatusCode::OK);
+        let anonymous = serve_with_cat_api(cat_api, &[]);
+        assert_eq!(get_status(anonymous, "/").await, StatusCode::OK);
+        assert_eq!(*keys.lock().unwrap(), [Some("secret".to_owned()), None]);
+    }
+
+    #[tokio::test]
+    async fn cat_png_is_a_png() {
+        let addr = serve_fake_cats(&[]);
+        let response = reqwest::get(format!("http://{addr}/cat.png?width=32"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "image/png");
+        let png = response.bytes().await.unwrap();
+        assert!(png.starts_with(b"\x89PNG\r\n\x1a\n"));
+        assert_eq!(image::load_from_memory(&png).unwrap().width(), 32);
+    }
+
+    #[tokio::test]
+    async fn slow_cat_apis_are_a_gateway_timeout() {
+        let cat_api = serve(Router::new().route(
+            "/v1/images/search",
+            get(|| async {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                Json(serde_json::json!([]))
+            }),
+        ));
+        let addr = serve_with_cat_api(cat_api, &[("UPSTREAM_TIMEOUT_SECS", "1")]);
+        let (status, body) = get_text(addr, "/").await;
+        assert_eq!(status, StatusCode::GATEWAY_TIMEOUT);
+        assert_eq!(body, "Upstream timed out");
+    }
+
+    #[tokio::test]
+    async fn downloads_the_same_url_once() {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let cdn = serve(Router::new().route(
+            "/cat.png",
+            get({
+                let hits = hits.clone();
+                move || async move {
+                    hits.fetch_add(1, Ordering::Relaxed);
+                    ([(header::CONTENT_TYPE, "image/png")], png())
+                }
+            }),
+        ));
+        let state = ServerState::new(&config(&[]));
+        let url = format!("http://{cdn}/cat.png");
+        let first = download_file(&state, &state.client, &url).await.unwrap();
+        let second = download_file(&state, &state.client, &url).await.unwrap();
+        assert_eq!(first, second);
+        assert_eq!(hits.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn grayscale_art_differs_from_colored_art() {
+        let addr = serve_fake_cats(&[]);
+        let client = reqwest::Client::new();
+        let html = |query: &'static str| {
+            let request = client
+                .get(format!("http://{addr}/{query}"))
+                .header(header::ACCEPT, "text/html")
+                .send();
+            async move {
+                let response = request.await.unwrap();
+                assert_eq!(response.status(), StatusCode::OK, "{query}");
+                response.text().await.unwrap()
+            }
+        };
+        let colored = html("").await;
+        let grayscale = html("?color=false").await;
+        assert_ne!(colored, grayscale);
+        assert_eq!(html("?grayscale=true").await, grayscale);
+        assert_eq!(
+            get_status(addr, "/?color=maybe").await,
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[tokio::test]
+    async fn compresses_art_for_clients_that_accept_gzip() {
+        let addr = serve_fake_cats(&[]);
+        let client = reqwest::Client::new();
+        let get = |encoding| {
+            client
+                .get(format!("http://{addr}/"))
+                .header(header::ACCEPT, "text/html")
+                .header(header::ACCEPT_ENCODING, encoding)
+                .send()
+        };
+        let plain = get("identity").await.unwrap();
+        assert!(!plain.headers().contains_key(header::CONTENT_ENCODING));
+        let gzipped = get("gzip").await.unwrap();
+        assert_eq!(gzipped.headers()[header::CONTENT_ENCODING], "gzip");
+        assert!(gzipped.headers()[header::CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .starts_with("text/html"));
+        let plain = plain.bytes().await.unwrap();
+        let gzipped = gzipped.bytes().await.unwrap();
+        assert!(gzipped.len() < plain.len());
+    }
+
+    #[tokio::test]
+    async fn errors_are_json_or_text_as_accepted() {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let addr = serve_with_cat_api(serve_cat_api(), &[]);
+        let response = reqwest::Client::new()
+            .get(format!("http://{addr}/"))
+            .header(header::ACCEPT, "application/json")
+            .header(
+                "traceparent",
+                "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            )
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "error": "Upstream request failed",
+                "trace_id": "4bf92f3577b34da6a3ce929d0e0e4736",
+            })
+        );
+
+        let (status, body) = get_text(addr, "/").await;
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+        assert_eq!(body, "Upstream request failed");
+
+        let verbose = serve_with_cat_api(serve_cat_api(), &[("VERBOSE_ERRORS", "true")]);
+        let (_, body) = get_text(verbose, "/").await;
+        assert!(body.contains("401"), "{body}");
+    }
+
+    #[tokio::test]
+    async fn unknown_routes_and_methods_get_friendly_errors() {
+        let addr = serve_fake_cats(&[]);
+        let client = reqwest::Client::new();
+        let (status, body) = get_text(addr, "/dogs-and-cats").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body, "Nothing here, try GET / for a cat");
+        let response = client
+            .get(format!("http://{addr}/dogs-and-cats"))
+            .header(header::ACCEPT, "application/json")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["error"], "Nothing here, try GET / for a cat");
+
+        let response = client
+            .post(format!("http://{addr}/"))
+            .header(header::ACCEPT, "application/json")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert!(
+            body["error"]
+                .as_str()
+                .unwrap()
+                .starts_with("Method not allowed"),
+            "{body}"
+        );
+    }
+
+    #[tokio::test]
+    async fn serves_a_long_lived_favicon() {
+        let config = config(&[]);
+        let addr = serve_app(&config, ServerState::new(&config));
+        let response = reqwest::get(format!("http://{addr}/favicon.ico"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "image/x-icon");
+        assert!(response.headers()[header::CACHE_CONTROL]
+            .to_str()
+            .unwrap()
+            .contains("max-age=604800"));
+        assert!(!response.bytes().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn cat_api_calls_share_a_connection() {
+        let image_url = format!("http://{}/cat.png", serve_png());
+        let peers = Arc::new(Mutex::new(Vec::new()));
+        let cat_api = serve(Router::new().route(
+            "/v1/images/search",
+            get({
+                let peers = peers.clone();
+                move |ConnectInfo(peer): ConnectInfo<SocketAddr>| async move {
+                    peers.lock().unwrap().push(peer);
+                    Json(serde_json::json!([{ "url": image_url }]))
+                }
+            }),
+        ));
+        let addr = serve_with_cat_api(cat_api, &[]);
+        for _ in 0..3 {
+            assert_eq!(get_status(addr, "/").await, StatusCode::OK);
+        }
+        let peers = peers.lock().unwrap();
+        assert_eq!(peers.len(), 3);
+        assert!(peers.iter().all(|peer| *peer == peers[0]), "{peers:?}");
+    }
+
+    #[tokio::test]
+    async fn draws_with_the_requested_characters() {
+        let addr = serve_fake_cats(&[]);
+        let (status, art) = get_text(addr, "/?chars=.%23&color=false").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(art.contains(['.', '#']), "{art}");
+        assert!(art.chars().all(|c| ".# \n".contains(c)), "{art}");
+
+        let (status, body) = get_text(addr, "/?chars=").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body, "chars must be between 1 and 64 characters long");
+        let too_long = "x".repeat(MAX_CHARACTERS + 1);
+        let (status, _) = get_text(addr, &format!("/?chars={too_long}")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn html_is_a_full_page_unless_raw() {
+        let addr = serve_fake_cats(&[]);
+        let client = reqwest::Client::new();
+        let html = |query: &'static str| {
+            let request = client
+                .get(format!("http://{addr}/{query}"))
+                .header(header::ACCEPT, "text/html")
+                .send();
+            async move { request.await.unwrap().text().await.unwrap() }
+        };
+        let page = html("").await;
+        assert!(page.starts_with("<!DOCTYPE html>"), "{page}");
+        assert!(page.contains("<html"));
+        let fragment = html("?raw=true").await;
+        assert!(fragment.starts_with("<pre>"), "{fragment}");
+        assert!(!fragment.contains("<html"), "{fragment}");
+        assert!(page.contains(&fragment));
+    }
+
+    #[tokio::test]
+    async fn oversized_downloads_are_payload_too_large() {
+        let limit = [("MAX_DOWNLOAD_BYTES", "64")];
+        for url in [
+            format!("http://{}/cat.png", serve_png()),
+            format!("http://{}/cat.png", serve_chunked_png(16)),
+        ] {
+            let addr = serve_cats_at(url.clone(), &limit);
+            let (status, body) = get_text(addr, "/").await;
+            assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE, "{url}");
+            assert_eq!(body, "Image is larger than 64 bytes");
+        }
+    }
+
+    #[tokio::test]
+    async fn reassembles_chunked_downloads() {
+        let state = ServerState::new(&config(&[]));
+        let url = format!("http://{}/cat.png", serve_chunked_png(7));
+        let bytes = download_file(&state, &state.client, &url).await.unwrap();
+        assert_eq!(bytes, png());
+    }
+
+    #[tokio::test]
+    async fn grids_skip_the_cats_that_failed() {
+        let cdn = serve_png();
+        let searches = Arc::new(AtomicUsize::new(0));
+        let cat_api = serve(Router::new().route(
+            "/v1/images/search",
+            get(move || async move {
+                // The first cat is gone by the time we go to download it.
+                let path = match searches.fetch_add(1, Ordering::Relaxed) {
+                    0 => "missing.png",
+                    _ => "cat.png",
+                };
+                Json(serde_json::json!([{ "url": format!("http://{cdn}/{path}") }]))
+            }),
+        ));
+        let addr = serve_with_cat_api(cat_api, &[]);
+        let response = reqwest::Client::new()
+            .get(format!("http://{addr}/?count=3"))
+            .header(header::ACCEPT, "text/html")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let page = response.text().await.unwrap();
+        assert_eq!(page.matches(r#"<div class="cat">"#).count(), 2);
+
+        assert_eq!(
+            get_status(addr, "/?count=10").await,
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[tokio::test]
+    async fn the_same_mocked_cat_is_the_same_art() {
+        #[derive(Deserialize)]
+        struct Search {
+            seed: Option<String>,
+        }
+
+        let image_url = format!("http://{}/cat.png", serve_png());
+        let seeds = Arc::new(Mutex::new(Vec::new()));
+        let cat_api = serve(Router::new().route(
+            "/v1/images/search",
+            get({
+                let seeds = seeds.clone();
+                move |Query(search): Query<Search>| async move {
+                    seeds.lock().unwrap().push(search.seed);
+                    Json(serde_json::json!([{ "url": image_url }]))
+                }
+            }),
+        ));
+        let addr = serve_with_cat_api(cat_api, &[("ART_CACHE_TTL_SECS", "0")]);
+        let (status, first) = get_text(addr, "/?seed=abc").await;
+        assert_eq!(status, StatusCode::OK);
+        let (_, second) = get_text(addr, "/?seed=abc").await;
+        assert_eq!(first, second);
+        assert_eq!(
+            *seeds.lock().unwrap(),
+            [Some("abc".to_owned()), Some("abc".to_owned())]
+        );
+    }
+
+    #[tokio::test]
+    async fn json_art_comes_with_its_metadata() {
+        let addr = serve_fake_cats(&[]);
+        let response = reqwest::get(format!("http://{addr}/?format=json"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+        let body: serde_json::Value = response.json().await.unwrap();
+        let art = body["art"].as_str().unwrap();
+        assert!(!art.trim().is_empty());
+        assert!(!art.contains('<'), "{art}");
+        assert!(body["source_url"].as_str().unwrap().ends_with("/cat.png"));
+        assert_eq!(body["width"], 16);
+        assert_eq!(body["height"], 16);
+    }
+
+    #[tokio::test]
+    async fn art_responses_describe_the_source_image() {
+        let addr = serve_fake_cats(&[]);
+        let response = reqwest::Client::new()
+            .get(format!("http://{addr}/"))
+            .header(header::ACCEPT, "text/plain")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let header = |name| response.headers()[name].to_str().unwrap();
+        assert_eq!(header("x-image-width").parse::<u32>().unwrap(), 16);
+        assert_eq!(header("x-image-height").parse::<u32>().unwrap(), 16);
+        assert!(header("x-source-url").ends_with("/cat.png"));
+    }
+
+    #[tokio::test]
+    async fn version_names_the_build() {
+        let config = config(&[]);
+        let addr = serve_app(&config, ServerState::new(&config));
+        let response = reqwest::get(format!("http://{addr}/version"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["version"], env!("CARGO_PKG_VERSION"));
+        assert!(body["git_sha"].is_string());
+        assert!(body["build_timestamp"].as_u64().unwrap() > 0);
+    }
+
+    #[tokio::test]
+    async fn loads_tls_certificates() {
+        let fixture = |name| {
+            PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+                .join("tests/fixtures")
+                .join(name)
+        };
+        let paths = TlsPaths {
+            cert: fixture("localhost.pem"),
+            key: fixture("localhost-key.pem"),
+        };
+        load_tls_config(&paths).await.unwrap();
+
+        let missing = TlsPaths {
+            cert: fixture("missing.pem"),
+            ..paths
+        };
+        let e = load_tls_config(&missing).await.err().unwrap();
+        assert!(e.to_string().contains("missing.pem"), "{e}");
+    }
+
+    #[tokio::test]
+    async fn slow_handlers_hit_the_request_timeout() {
+        let config = config(&[
+            ("REQUEST_TIMEOUT_SECS", "2"),
+            ("PIPELINE_TIMEOUT_SECS", "1"),
+        ]);
+        let mut state = ServerState::new(&config);
+        state.cat_images = Box::new(SlowCats);
+        // `/cat.png` doesn't go through the art pipeline and its deadline.
+        let addr = serve_app(&config, state);
+        let (status, body) = get_text(addr, "/cat.png").await;
+        assert_eq!(status, StatusCode::GATEWAY_TIMEOUT);
+        assert_eq!(body, "Request timed out");
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_the_bundled_cat() {
+        let addr = serve_with_cat_api(serve_cat_api(), &[("FALLBACK_ENABLED", "true")]);
+        let response = reqwest::Client::new()
+            .get(format!("http://{addr}/"))
+            .header(header::ACCEPT, "text/plain")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["x-fallback"], "true");
+        assert!(!response.text().await.unwrap().trim().is_empty());
+
+        let addr = serve_with_cat_api(serve_cat_api(), &[]);
+        assert_eq!(get_status(addr, "/").await, StatusCode::BAD_GATEWAY);
+    }
+
+    #[tokio::test]
+    async fn malformed_cat_api_responses_are_a_bad_gateway() {
+        let cat_api = serve(Router::new().route(
+            "/v1/images/search",
+            get(|| async { ([(header::CONTENT_TYPE, "application/json")], "[{\"url\":") }),
+        ));
+        let addr = serve_with_cat_api(cat_api, &[]);
+        let (status, body) = get_text(addr, "/").await;
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+        assert_eq!(body, "Upstream request failed");
+
+        let addr = serve_with_cat_api(cat_api, &[("VERBOSE_ERRORS", "true")]);
+        let (_, body) = get_text(addr, "/").await;
+        assert!(body.contains("decoding response body"), "{body}");
+    }
+
+    #[tokio::test]
+    async fn corrupt_images_are_our_problem() {
+        let cdn = serve(Router::new().route(
+            "/cat.png",
+            get(|| async {
+                let mut png = png();
+                png[40..60].fill(0);
+                ([(header::CONTENT_TYPE, "image/png")], png)
+            }),
+        ));
+        let addr = serve_cats_at(format!("http://{cdn}/cat.png"), &[]);
+        assert_eq!(
+            get_status(addr, "/").await,
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+
+    #[tokio::test]
+    async fn html_instead_of_an_image_is_a_bad_gateway() {
+        let cdn = serve(Router::new().route(
+            "/cat.png",
+            get(|| async { axum::response::Html("<h1>Cat not found</h1>") }),
+        ));
+        let addr = serve_cats_at(format!("http://{cdn}/cat.png"), &[]);
+        let (status, body) = get_text(addr, "/").await;
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+        assert!(body.contains("text/html"), "{body}");
+
+        // Without a content type, the decoder gets its say.
+        let cdn = serve(Router::new().route(
+            "/cat.png",
+            get(|| async { Response::new(axum::body::boxed(axum::body::Full::from(png()))) }),
+        ));
+        let addr = serve_cats_at(format!("http://{cdn}/cat.png"), &[]);
+        assert_eq!(get_status(addr, "/").await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn blocking_conversions_draw_the_same_art() {
+        let state = fake_cats(&config(&[]));
+        let options = ArtOptions {
+            format: ArtFormat::PlainText,
+            ..Default::default()
+        };
+        let art = get_ascii_art(&state, &CatSource::default(), &options)
+            .await
+            .unwrap();
+        let image = image::load_from_memory(&png()).unwrap();
+        assert_eq!(art.text, options.convert(&image, usize::MAX).unwrap());
+        assert_eq!((art.width, art.height), (16, 16));
+    }
+
+    #[tokio::test]
+    async fn streams_cats_as_events() {
+        let addr = serve_fake_cats(&[("STREAM_INTERVAL_SECS", "1")]);
+        let response = reqwest::get(format!("http://{addr}/stream")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "text/event-stream"
+        );
+
+        let mut body = response.bytes_stream();
+        let mut received = String::new();
+        while !received.contains("\n\n") {
+            let chunk = body.next().await.unwrap().unwrap();
+            received.push_str(std::str::from_utf8(&chunk).unwrap());
+        }
+        let event = received.split("\n\n").next().unwrap();
+        assert!(event.lines().any(|line| line == "event:cat"), "{event}");
+        let data = event
+            .lines()
+            .find_map(|line| line.strip_prefix("data:"))
+            .unwrap();
+        let cat: serde_json::Value = serde_json::from_str(data).unwrap();
+        assert!(!cat["art"].as_str().unwrap().is_empty());
+        assert!(cat["source_url"].as_str().unwrap().ends_with("/cat.png"));
+    }
+
+    #[tokio::test]
+    async fn ws_answers_commands_and_survives_bad_ones() {
+        use futures_util::SinkExt;
+        use tokio_tungstenite::tungstenite::Message;
+
+        let addr = serve_fake_cats(&[]);
+        let (mut socket, _) = tokio_tungstenite::connect_async(format!("ws://{addr}/ws"))
+            .await
+            .unwrap();
+        let mut replies = Vec::new();
+        for command in ["meow", r#"{"action":"cat","width":40}"#] {
+            socket
+                .send(Message::Text(command.to_owned()))
+                .await
+                .unwrap();
+            match socket.next().await.unwrap().unwrap() {
+                Message::Text(reply) => replies.push(reply),
+                other => panic!("expected a text frame, got {other:?}"),
+            }
+        }
+        let error = ws_error(&replies[0]).unwrap();
+        assert!(error.starts_with("invalid command"), "{error}");
+        assert_eq!(ws_error(&replies[1]), None, "{}", replies[1]);
+        assert!(!replies[1].trim().is_empty());
+    }
+
+    #[tokio::test]
+    async fn archives_the_cat_and_its_art() {
+        let dir = std::env::temp_dir().join(format!("catscii-archived-{}", std::process::id()));
+        let addr = serve_fake_cats(&[("ARCHIVE_DIR", dir.to_str().unwrap())]);
+        assert_eq!(get_status(addr, "/").await, StatusCode::OK);
+
+        // Written off the request's path, so give it a moment.
+        let mut names = Vec::new();
+        for _ in 0..50 {
+            names = std::fs::read_dir(&dir)
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+                .collect();
+            if names.len() == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(names.len(), 2, "{names:?}");
+        names.sort_by_key(|name| name.ends_with(".txt"));
+        assert!(names[0].ends_with(".png"), "{names:?}");
+        assert!(names[1].ends_with(".txt"), "{names:?}");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn stats_count_requests() {
+        let addr = serve_fake_cats(&[]);
+        for _ in 0..2 {
+            assert_eq!(get_status(addr, "/").await, StatusCode::OK);
+        }
+        let stats: serde_json::Value = reqwest::get(format!("http://{addr}/stats"))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(stats["requests"], 2);
+        assert_eq!(stats["successes"], 2);
+        assert_eq!(stats["upstream_failures"], 0);
+        // Same cat both times, so drawn once.
+        assert_eq!(stats["cache_hits"], 1);
+        assert_eq!(stats["art_cache_entries"], 1);
+    }
+
+    /// Keeps what would have gone to Sentry.
+    #[derive(Default)]
+    struct CapturedEnvelopes(Mutex<Vec<sentry::Envelope>>);
+
+    impl sentry::Transport for CapturedEnvelopes {
+        fn send_envelope(&self, envelope: sentry::Envelope) {
+            self.0.lock().unwrap().push(envelope);
+        }
+    }
+
+    #[tokio::test]
+    async fn errors_reach_sentry_with_the_steps_before_them() {
+        let cdn = serve(Router::new().route(
+            "/cat.png",
+            get(|| async {
+                let mut png = png();
+                png[40..60].fill(0);
+                ([(header::CONTENT_TYPE, "image/png")], png)
+            }),
+        ));
+        let config = config(&[]);
+        let mut state = ServerState::new(&config);
+        state.cat_images = Box::new(FakeCats {
+            url: format!("http://{cdn}/cat.png"),
+        });
+
+        let envelopes = Arc::new(CapturedEnvelopes::default());
+        let client = sentry::Client::from(sentry::ClientOptions {
+            dsn: Some("https://public@sentry.invalid/1".parse().unwrap()),
+            transport: Some(Arc::new(envelopes.clone())),
+            ..Default::default()
+        });
+        let hub = Arc::new(sentry::Hub::new(
+            Some(Arc::new(client)),
+            Arc::new(sentry::Scope::default()),
+        ));
+        async {
+            let e = get_ascii_art(&state, &CatSource::default(), &ArtOptions::default())
+                .await
+                .err()
+                .unwrap();
+            error_response(&e, &HeaderMap::new(), false);
+        }
+        .bind_hub(hub)
+        .await;
+
+        let envelopes = envelopes.0.lock().unwrap();
+        let event = envelopes
+            .iter()
+            .find_map(|envelope| envelope.event())
+            .unwrap();
+        let trail: Vec<_> = event
+            .breadcrumbs
+            .iter()
+            .filter_map(|breadcrumb| breadcrumb.message.as_deref())
+            .collect();
+        assert_eq!(trail.len(), 2, "{trail:?}");
+        assert!(trail[0].starts_with("Got image URL"), "{trail:?}");
+        assert!(trail[1].starts_with("Downloaded"), "{trail:?}");
+    }
+
+    #[tokio::test]
+    async fn sentry_errors_are_tagged_with_the_trace_id() {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "traceparent",
+            HeaderValue::from_static("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"),
+        );
+        let span = start_request_span("root_get", &headers);
+
+        let envelopes = Arc::new(CapturedEnvelopes::default());
+        let client = sentry::Client::from(sentry::ClientOptions {
+            dsn: Some("https://public@sentry.invalid/1".parse().unwrap()),
+            transport: Some(Arc::new(envelopes.clone())),
+            ..Default::default()
+        });
+        let hub = Arc::new(sentry::Hub::new(
+            Some(Arc::new(client)),
+            Arc::new(sentry::Scope::default()),
+        ));
+        async {
+            let e = color_eyre::eyre::eyre!("forced");
+            error_response(&e, &HeaderMap::new(), false);
+        }
+        .with_context(Context::current_with_span(span))
+        .bind_hub(hub)
+        .await;
+
+        let envelopes = envelopes.0.lock().unwrap();
+        let event = envelopes
+            .iter()
+            .find_map(|envelope| envelope.event())
+            .unwrap();
+        assert_eq!(
+            event.tags.get("trace_id").map(String::as_str),
+            Some("4bf92f3577b34da6a3ce929d0e0e4736")
+        );
+    }
+
+    #[tokio::test]
+    async fn ansi_art_is_true_color() {
+        force_ansi_colors();
+        let addr = serve_fake_cats(&[]);
+        let response = reqwest::get(format!("http://{addr}/?target=ansi"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers()[header::CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .starts_with("text/plain"));
+        let art = response.text().await.unwrap();
+        // 24-bit foreground colors, as in `ESC[38;2;R;G;Bm`.
+        assert!(art.contains("\x1b[38;2;"), "{art:?}");
+
+        assert_eq!(
+            get_status(addr, "/?target=vt100").await,
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[tokio::test]
+    async fn slow_pipelines_hit_their_deadline() {
+        let config = config(&[("PIPELINE_TIMEOUT_SECS", "1")]);
+        let mut state = ServerState::new(&config);
+        state.cat_images = Box::new(SlowCats);
+        let addr = serve_app(&config, state);
+        let (status, body) = get_text(addr, "/").await;
+        assert_eq!(status, StatusCode::GATEWAY_TIMEOUT);
+        assert_eq!(body, "Couldn't make art within 1s");
+    }
+
+    #[tokio::test]
+    async fn empty_downloads_are_a_bad_gateway() {
+        let cdn = serve(Router::new().route(
+            "/cat.png",
+            get(|| async { [(header::CONTENT_TYPE, "image/png")] }),
+        ));
+        let addr = serve_cats_at(format!("http://{cdn}/cat.png"), &[]);
+        let (status, body) = get_text(addr, "/").await;
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+        assert_eq!(body, "Image download was empty");
+
+        let cdn = serve(Router::new().route(
+            "/cat.png",
+            get(|| async { ([(header::CONTENT_TYPE, "image/png")], &b"\x89PNG"[..]) }),
+        ));
+        let addr = serve_cats_at(format!("http://{cdn}/cat.png"), &[]);
+        let (status, body) = get_text(addr, "/").await;
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+        assert_eq!(body, "Image download was only 4 bytes");
+    }
+
+    #[tokio::test]
+    async fn downloads_are_plain_text_attachments() {
+        let addr = serve_fake_cats(&[]);
+        let response = reqwest::get(format!("http://{addr}/?download=true"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_DISPOSITION],
+            r#"attachment; filename="cat.txt""#
+        );
+        assert!(response.headers()[header::CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .starts_with("text/plain"));
+        let art = response.text().await.unwrap();
+        assert!(!art.contains('<'), "{art}");
+    }
+
+    #[test]
+    fn attachment_names_are_sanitized() {
+        assert_eq!(attachment_name("cat"), "cat");
+        assert_eq!(attachment_name("../\"evil\"\r\n"), "evil");
+        assert_eq!(attachment_name("日本"), "art");
+        assert_eq!(attachment_name(&"x".repeat(100)).len(), MAX_ATTACHMENT_NAME);
+    }
+
+    #[tokio::test]
+    async fn repeat_clients_get_not_modified() {
+        let addr = serve_fake_cats(&[]);
+        let client = reqwest::Client::new();
+        let get = |etag: Option<&str>| {
+            let mut request = client
+                .get(format!("http://{addr}/"))
+                .header(header::ACCEPT, "text/plain");
+            if let Some(etag) = etag {
+                request = request.header(header::IF_NONE_MATCH, etag);
+            }
+            request.send()
+        };
+        let first = get(None).await.unwrap();
+        assert_eq!(first.status(), StatusCode::OK);
+        let etag = first.headers()[header::ETAG].to_str().unwrap().to_owned();
+
+        let again = get(Some(&etag)).await.unwrap();
+        assert_eq!(again.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(again.headers()[header::ETAG], etag.as_str());
+        assert!(again.bytes().await.unwrap().is_empty());
+
+        let stale = get(Some("\"0000000000000000\"")).await.unwrap();
+        assert_eq!(stale.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn pages_follow_the_color_scheme_hint() {
+        let addr = serve_fake_cats(&[]);
+        let client = reqwest::Client::new();
+        let page = |scheme: &'static str| {
+            let request = client
+                .get(format!("http://{addr}/"))
+                .header(header::ACCEPT, "text/html")
+                .header("sec-ch-prefers-color-scheme", scheme)
+                .send();
+            async move { request.await.unwrap().text().await.unwrap() }
+        };
+        let light = page("\"light\"").await;
+        assert!(light.contains("background: #fafafa;"));
+        assert_ne!(light, page("\"dark\"").await);
+    }
+
+    #[tokio::test]
+    async fn prefetching_fills_the_art_cache() {
+        let state = Arc::new(fake_cats(&config(&[])));
+        prefetch(state.clone()).await;
+        assert_eq!(state.art_cache.len(), 1);
+
+        // A failed warmup only leaves the cache cold.
+        let mut state = ServerState::new(&config(&[]));
+        state.cat_images = Box::new(FakeCats {
+            url: "http://127.0.0.1:1/cat.png".to_owned(),
+        });
+        let state = Arc::new(state);
+        prefetch(state.clone()).await;
+        assert_eq!(state.art_cache.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn reducing_colors_flattens_the_art() {
+        let addr = serve_fake_cats(&[]);
+        let client = reqwest::Client::new();
+        let colors_in = |query: &'static str| {
+            let request = client
+                .get(format!("http://{addr}/{query}"))
+                .header(header::ACCEPT, "text/html")
+                .send();
+            async move {
+                let response = request.await.unwrap();
+                assert_eq!(response.status(), StatusCode::OK, "{query}");
+                let html = response.text().await.unwrap();
+                html.split("color: #")
+                    .skip(1)
+                    .map(|rest| rest[..6].to_owned())
+                    .collect::<std::collections::HashSet<_>>()
+                    .len()
+            }
+        };
+        let full = colors_in("").await;
+        let reduced = colors_in("?colors=2").await;
+        assert!(reduced <= 2, "{reduced}");
+        assert!(reduced < full, "{reduced} vs {full}");
+        for colors in ["1", "65", "many"] {
+            let (status, body) = get_text(addr, &format!("/?colors={colors}")).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            assert_eq!(body, "colors must be an integer between 2 and 64");
+        }
+    }
+
+    #[tokio::test]
+    async fn serves_http2_with_prior_knowledge() {
+        let config = config(&[("HTTP2_ONLY", "true"), ("STREAM_INTERVAL_SECS", "1")]);
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = app(&config, Arc::new(fake_cats(&config)), Arc::new(|_| Ok(())));
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .http2_only(config.http2_only)
+                .serve(app.into_make_service_with_connect_info::<SocketAddr>()),
+        );
+
+        let client = reqwest::Client::builder()
+            .http2_prior_knowledge()
+            .build()
+            .unwrap();
+        let response = client.get(format!("http://{addr}/")).send().await.unwrap();
+        assert_eq!(response.version(), reqwest::Version::HTTP_2);
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(!response.text().await.unwrap().is_empty());
+
+        let response = client
+            .get(format!("http://{addr}/stream"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let mut body = response.bytes_stream();
+        let first = body.next().await.unwrap().unwrap();
+        assert!(!first.is_empty());
+
+        // HTTP/1.1 clients aren't understood.
+        assert!(reqwest::get(format!("http://{addr}/")).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn source_urls_are_only_given_for_real_cats() {
+        let image_url = format!("http://{}/cat.png?size=full", serve_png());
+        let addr = serve_cats_at(image_url.clone(), &[]);
+        let response = reqwest::get(format!("http://{addr}/")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["x-source-url"], image_url.as_str());
+
+        let addr = serve_with_cat_api(serve_cat_api(), &[("FALLBACK_ENABLED", "true")]);
+        let response = reqwest::get(format!("http://{addr}/")).await.unwrap();
+        assert_eq!(response.headers()["x-fallback"], "true");
+        assert!(response.headers().get("x-source-url").is_none());
+
+        let addr = serve_with_cat_api(serve_cat_api(), &[]);
+        let response = reqwest::get(format!("http://{addr}/")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+        assert!(response.headers().get("x-source-url").is_none());
+    }
+
+    #[tokio::test]
+    async fn empty_cat_api_results_are_a_bad_gateway() {
+        let cat_api = serve(Router::new().route(
+            "/v1/images/search",
+            get(|| async { Json(serde_json::json!([])) }),
+        ));
+        let addr = serve_with_cat_api(cat_api, &[]);
+        let (status, body) = get_text(addr, "/").await;
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+        assert_eq!(body, "The Cat API didn't send any cats, try again");
+    }
+
+    #[tokio::test]
+    async fn metrics_time_the_cat_api_and_downloads() {
+        let addr = serve_with_cat_api(serve_cat_api(), &[("CAT_API_KEY", "secret")]);
+        assert_eq!(get_status(addr, "/").await, StatusCode::OK);
+        let (_, metrics) = get_text(addr, "/metrics").await;
+        for histogram in [
+            "catscii_cat_api_duration_seconds",
+            "catscii_download_duration_seconds",
+        ] {
+            assert!(
+                metrics.contains(&format!(r#"{histogram}_count{{outcome="ok"}}"#)),
+                "{metrics}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn pages_bring_their_own_styles_unless_raw() {
+        let addr = serve_fake_cats(&[]);
+        let client = reqwest::Client::new();
+        let html = |query: &'static str| {
+            let request = client
+                .get(format!("http://{addr}/{query}"))
+                .header(header::ACCEPT, "text/html")
+                .send();
+            async move { request.await.unwrap().text().await.unwrap() }
+        };
+        let page = html("").await;
+        let style = page
+            .split_once("<style>")
+            .and_then(|(_, rest)| rest.split_once("</style>"))
+            .map(|(style, _)| style)
+            .unwrap_or_else(|| panic!("{page}"));
+        for property in [
+            "font-family:",
+            "line-height:",
+            "letter-spacing:",
+            "background:",
+        ] {
+            assert!(style.contains(property), "{property} in {style}");
+        }
+        let fragment = html("?raw=true").await;
+        assert!(!fragment.contains("<style"), "{fragment}");
+    }
+
+    #[tokio::test]
+    async fn art_is_cacheable_per_accept_and_color_scheme() {
+        let addr = serve_fake_cats(&[("ART_MAX_AGE_SECS", "42")]);
+        let response = reqwest::Client::new()
+            .get(format!("http://{addr}/?width=40"))
+            .header(header::ACCEPT, "text/plain")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CACHE_CONTROL],
+            "public, max-age=42"
+        );
+        let vary: Vec<_> = response.headers().get_all(header::VARY).iter().collect();
+        for name in ["accept", "sec-ch-prefers-color-scheme", "origin"] {
+            assert!(
+                vary.iter().any(|value| *value == name),
+                "{name} in {vary:?}"
+            );
+        }
+
+        // The fallback cat isn't worth keeping.
+        let addr = serve_with_cat_api(serve_cat_api(), &[("FALLBACK_ENABLED", "true")]);
+        let response = reqwest::get(format!("http://{addr}/")).await.unwrap();
+        assert_eq!(response.headers()[header::CACHE_CONTROL], "no-store");
+    }
+
+    #[tokio::test]
+    async fn random_redirects_to_the_cat_image() {
+        // Nothing listens there, so any download would fail.
+        let image_url = "http://127.0.0.1:1/cat.png";
+        let addr = serve_cats_at(image_url.to_owned(), &[]);
+        let response = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap()
+            .get(format!("http://{addr}/random"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FOUND);
+        assert_eq!(response.headers()[header::LOCATION], image_url);
+        assert_eq!(response.headers()[header::CACHE_CONTROL], "no-store");
+    }
+
+    #[tokio::test]
+    async fn borders_frame_the_art() {
+        let addr = serve_fake_cats(&[]);
+        let (_, plain) = get_text(addr, "/?width=30").await;
+        let (status, framed) = get_text(addr, "/?width=30&border=true").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(!plain.contains('╔'), "{plain}");
+        let lines: Vec<_> = framed.lines().collect();
+        assert!(lines[0].starts_with('╔'), "{framed}");
+        assert!(lines.last().unwrap().starts_with('╚'), "{framed}");
+        assert!(lines[1].starts_with('║'), "{framed}");
+        assert_eq!(lines.len(), plain.lines().count() + 2, "{framed}");
+
+        let (status, body) = get_text(addr, "/?border=maybe").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body.contains("border"), "{body}");
+        let (status, body) = get_text(addr, "/?border=true&mode=braille").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body, "border only works in ascii mode");
+    }
+
+    #[tokio::test]
+    async fn refetches_truncated_jpegs() {
+        let mut jpeg = Vec::new();
+        image::load_from_memory(&png())
+            .unwrap()
+            .write_to(
+                &mut std::io::Cursor::new(&mut jpeg),
+                image::ImageOutputFormat::Jpeg(90),
+            )
+            .unwrap();
+        assert!(!looks_truncated(&jpeg));
+        let truncated = jpeg[..jpeg.len() / 2].to_vec();
+        assert!(looks_truncated(&truncated));
+
+        let hits = Arc::new(AtomicUsize::new(0));
+        let cdn = serve(Router::new().route(
+            "/cat.jpg",
+            get({
+                let hits = hits.clone();
+                move || async move {
+                    let body = match hits.fetch_add(1, Ordering::SeqCst) {
+                        0 => truncated,
+                        _ => jpeg,
+                    };
+                    ([(header::CONTENT_TYPE, "image/jpeg")], body)
+                }
+            }),
+        ));
+        let addr = serve_cats_at(format!("http://{cdn}/cat.jpg"), &[]);
+        assert_eq!(get_status(addr, "/").await, StatusCode::OK);
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn sizes_pick_preset_widths() {
+        let cdn = serve(Router::new().route(
+            "/cat.png",
+            get(|| async {
+                let image = image::RgbImage::from_fn(400, 200, |x, y| {
+                    image::Rgb([(x % 256) as u8, (y % 256) as u8, 128])
+                });
+                let mut png = Vec::new();
+                image::DynamicImage::ImageRgb8(image)
+                    .write_to(
+                        &mut std::io::Cursor::new(&mut png),
+                        image::ImageOutputFormat::Png,
+                    )
+                    .unwrap();
+                ([(header::CONTENT_TYPE, "image/png")], png)
+            }),
+        ));
+        let addr = serve_cats_at(format!("http://{cdn}/cat.png"), &[]);
+        for (size, columns) in [("small", 60), ("medium", 120), ("large", 200)] {
+            let (status, art) = get_text(addr, &format!("/?size={size}")).await;
+            assert_eq!(status, StatusCode::OK, "{size}");
+            let widest = art.lines().map(|line| line.chars().count()).max().unwrap();
+            assert!(widest.abs_diff(columns) <= 2, "{size}: {widest} columns");
+        }
+
+        let (status, body) = get_text(addr, "/?size=huge").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body, "size must be small, medium or large");
+    }
+
+    #[tokio::test]
+    async fn verbose_health_details_the_dependencies() {
+        let addr = serve_with_cat_api(serve_cat_api(), &[("CAT_API_KEY", "secret")]);
+        let health = || async {
+            let response = reqwest::get(format!("http://{addr}/health?verbose=true"))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            response.json::<serde_json::Value>().await.unwrap()
+        };
+        let before = health().await;
+        assert_eq!(before["status"], "ok");
+        assert_eq!(before["honeycomb"], false);
+        assert!(before["sentry"].is_boolean(), "{before}");
+        assert!(before["cat_api_last_success"].is_null(), "{before}");
+        assert_eq!(before["art_cache_entries"], 0);
+        assert_eq!(before["download_cache_entries"], 0);
+
+        assert_eq!(get_status(addr, "/").await, StatusCode::OK);
+        let after = health().await;
+        assert!(
+            after["cat_api_last_success"].as_u64().unwrap() > 0,
+            "{after}"
+        );
+        assert_eq!(after["art_cache_entries"], 1);
+        assert_eq!(after["download_cache_entries"], 1);
+
+        assert_eq!(
+            get_status(addr, "/health?verbose=maybe").await,
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[tokio::test]
+    async fn rate_limited_clients_get_an_error_body() {
+        let addr = serve_fake_cats(&[("RATE_LIMIT_BURST", "1"), ("RATE_LIMIT_PER_SECOND", "0.01")]);
+        let client = reqwest::Client::new();
+        let get = || {
+            client
+                .get(format!("http://{addr}/"))
+                .header(header::ACCEPT, "application/json")
+                .send()
+        };
+        assert_eq!(get().await.unwrap().status(), StatusCode::OK);
+        let limited = get().await.unwrap();
+        assert_eq!(limited.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(limited.headers()[header::RETRY_AFTER], "100");
+        let body: serde_json::Value = limited.json().await.unwrap();
+        assert_eq!(body["error"], "Too many requests, slow down");
+    }
+
+    #[tokio::test]
+    async fn art_routes_share_the_rate_limit() {
+        let addr = serve_fake_cats(&[("RATE_LIMIT_BURST", "1"), ("RATE_LIMIT_PER_SECOND", "0.01")]);
+        assert_eq!(get_status(addr, "/cat/known").await, StatusCode::OK);
+        for path in ["/", "/cat/known", "/dog", "/convert", "/stream", "/ws"] {
+            assert_eq!(
+                get_status(addr, path).await,
+                StatusCode::TOO_MANY_REQUESTS,
+                "{path}"
+            );
+        }
+        assert_eq!(get_status(addr, "/health").await, StatusCode::OK);
+    }
+
+    const WS_CAT: &str = r#"{"action":"cat"}"#;
+
+    /// Whether `reply` is one of `ws_reply`'s JSON errors rather than art.
+    fn ws_error(reply: &str) -> Option<String> {
+        let reply: serde_json::Value = serde_json::from_str(reply).ok()?;
+        Some(reply["error"].as_str()?.to_owned())
+    }
+
+    #[tokio::test]
+    async fn ws_cats_wait_for_a_permit_and_are_counted() {
+        let state = fake_cats(&config(&[("MAX_IN_FLIGHT", "1")]));
+        let held = state.art_permits.clone().acquire_owned().await.unwrap();
+        let busy = ws_reply(&state, WS_CAT).await;
+        assert_eq!(
+            ws_error(&busy).as_deref(),
+            Some("Too busy right now, try again shortly")
+        );
+
+        drop(held);
+        let art = ws_reply(&state, WS_CAT).await;
+        assert_eq!(ws_error(&art), None, "{art}");
+        assert_eq!(state.stats.requests.load(Ordering::Relaxed), 1);
+        assert_eq!(state.stats.successes.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn ws_cats_have_a_deadline() {
+        let config = config(&[("PIPELINE_TIMEOUT_SECS", "1")]);
+        let mut state = ServerState::new(&config);
+        state.cat_images = Box::new(SlowCats);
+        let reply = ws_reply(&state, WS_CAT).await;
+        assert_eq!(
+            ws_error(&reply).as_deref(),
+            Some("Couldn't make art within 1s")
+        );
+        assert_eq!(state.stats.upstream_failures.load(Ordering::Relaxed), 1);
+    }
+
+    fn accept(value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT, HeaderValue::from_static(value));
+        headers
+    }
+
+    #[test]
+    fn accept_quality_reads_q_values() {
+        let headers = accept("text/html;q=0.5, text/plain, application/json ; q=0.8");
+        assert_eq!(accept_quality(&headers, "text/html"), 0.5);
+        assert_eq!(accept_quality(&headers, "text/plain"), 1.0);
+        assert_eq!(accept_quality(&headers, "application/json"), 0.8);
+        assert_eq!(accept_quality(&headers, "image/png"), 0.0);
+        assert_eq!(accept_quality(&accept("*/*"), "text/plain"), 0.0);
+        assert_eq!(accept_quality(&HeaderMap::new(), "text/html"), 0.0);
+    }
+
+    #[test]
+    fn html_unless_plain_text_is_preferred() {
+        assert_eq!(ArtFormat::from_accept(&HeaderMap::new()), ArtFormat::Html);
+        assert_eq!(ArtFormat::from_accept(&accept("*/*")), ArtFormat::Html);
+        assert_eq!(
+            ArtFormat::from_accept(&accept("text/html, text/plain")),
+            ArtFormat::Html
+        );
+        assert_eq!(
+            ArtFormat::from_accept(&accept("text/html;q=0.9, text/plain")),
+            ArtFormat::PlainText
+        );
+        assert_eq!(
+            ArtFormat::from_accept(&accept("text/plain")),
+            ArtFormat::PlainText
+        );
+    }
+
+    #[test]
+    fn truncates_art_at_a_line_break() {
+        let art = "aaaa\nbbbb\ncccc\n";
+        let truncated = truncate_art(art, 10 + TRUNCATED_NOTICE.len());
+        assert_eq!(truncated, format!("aaaa\nbbbb{TRUNCATED_NOTICE}"));
+        assert!(truncated.chars().count() <= 10 + TRUNCATED_NOTICE.len());
+    }
+
+    #[test]
+    fn truncates_by_characters_not_bytes() {
+        let art = "ééé\nééé\n";
+        let truncated = truncate_art(art, 5 + TRUNCATED_NOTICE.len());
+        assert_eq!(truncated, format!("ééé{TRUNCATED_NOTICE}"));
+    }
+
+    #[test]
+    fn capped_conversions_fit() {
+        let image = image::load_from_memory(&png()).unwrap();
+        let options = ArtOptions {
+            format: ArtFormat::PlainText,
+            width: Some(100),
+            ..Default::default()
+        };
+        let uncapped = options.convert(&image, usize::MAX).unwrap();
+        let max_chars = uncapped.chars().count() / 3;
+        let capped = options.convert_capped(&image, max_chars).unwrap();
+        assert!(capped.chars().count() <= max_chars);
+    }
+
+    #[test]
+    fn downscales_big_images_keeping_their_shape() {
+        let big = image::DynamicImage::new_rgb8(3000, 1500);
+        let resized = downscale(big, 1000);
+        assert_eq!((resized.width(), resized.height()), (1000, 500));
+        let small = image::DynamicImage::new_rgb8(300, 150);
+        let kept = downscale(small, 1000);
+        assert_eq!((kept.width(), kept.height()), (300, 150));
+    }
+
+    #[test]
+    fn aspect_ratios_change_the_row_count() {
+        let image = image::load_from_memory(&png()).unwrap();
+        let rows = |aspect_ratio| {
+            let options = ArtOptions {
+                format: ArtFormat::PlainText,
+                width: Some(32),
+                aspect_ratio,
+                ..Default::default()
+            };
+            options.convert(&image, usize::MAX).unwrap().lines().count()
+        };
+        assert!(rows(50) < rows(100), "{} vs {}", rows(50), rows(100));
+        assert_eq!(parse_aspect_ratio("0.5"), Ok(50));
+    }
+
+    #[test]
+    fn inverting_flips_the_ramp() {
+        let image = image::load_from_memory(&png()).unwrap();
+        let options = ArtOptions {
+            format: ArtFormat::PlainText,
+            color: false,
+            ..Default::default()
+        };
+        let inverted = ArtOptions {
+            invert: true,
+            ..options.clone()
+        };
+        assert_ne!(
+            options.convert(&image, usize::MAX).unwrap(),
+            inverted.convert(&image, usize::MAX).unwrap()
+        );
+    }
+
+    #[test]
+    fn dithering_changes_the_art() {
+        let image = image::load_from_memory(&png()).unwrap();
+        let options = ArtOptions {
+            format: ArtFormat::PlainText,
+            ..Default::default()
+        };
+        let dithered = ArtOptions {
+            dither: true,
+            ..options.clone()
+        };
+        assert_ne!(
+            options.convert(&image, usize::MAX).unwrap(),
+            dithered.convert(&image, usize::MAX).unwrap()
+        );
+    }
+
+    #[test]
+    fn edge_detection_changes_the_art() {
+        let image = image::load_from_memory(&png()).unwrap();
+        let options = ArtOptions {
+            format: ArtFormat::PlainText,
+            ..Default::default()
+        };
+        let edges = ArtOptions {
+            edges: true,
+            ..options.clone()
+        };
+        assert_ne!(
+            options.convert(&image, usize::MAX).unwrap(),
+            edges.convert(&image, usize::MAX).unwrap()
+        );
+    }
+
+    #[test]
+    fn every_preset_draws_a_cat() {
+        let image = image::load_from_memory(&png()).unwrap();
+        let defaults = ArtOptions {
+            format: ArtFormat::PlainText,
+            ..Default::default()
+        };
+        for preset in preset::PRESETS {
+            let options = preset.apply(&defaults);
+            let art = options.convert(&image, usize::MAX).unwrap();
+            assert!(!art.trim().is_empty(), "{} drew nothing", preset.name);
+            if let (Some(ramp), false) = (&options.characters, options.color) {
+                assert!(
+                    art.chars().all(|c| c.is_whitespace() || ramp.contains(c)),
+                    "{} strayed from its ramp",
+                    preset.name
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn telemetry_is_optional() {
+        assert!(init_sentry(None).is_none());
+        assert!(init_honeycomb(None).is_none());
+    }
+
+    #[test]
+    fn request_spans_join_the_callers_trace() {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "traceparent",
+            HeaderValue::from_static("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"),
+        );
+        let span = start_request_span("root_get", &headers);
+        assert_eq!(
+            span.span_context().trace_id().to_string(),
+            "4bf92f3577b34da6a3ce929d0e0e4736"
+        );
+        let fresh = start_request_span("root_get", &HeaderMap::new());
+        assert_ne!(
+            fresh.span_context().trace_id(),
+            span.span_context().trace_id()
+        );
+    }
+
+    #[test]
+    fn logs_in_either_format_under_the_filter() {
+        for format in ["json", "pretty"] {
+            let format: LogFormat = format.parse().unwrap();
+            let filter: Targets = "catscii=debug,hyper=off".parse().unwrap();
+            let _guard = tracing_subscriber::registry()
+                .with(filter)
+                .with(log_output(format))
+                .set_default();
+            tracing::info!("hello");
+            tracing::debug!(target: "hyper", "filtered out");
+        }
+    }
+
+    #[test]
+    fn runtimes_have_the_configured_thread_counts() {
+        /// How many of 8 sleepy jobs `spawn` ever has running at once.
+        fn peak_concurrency(spawn: impl Fn(Box<dyn FnOnce() + Send>)) -> usize {
+            let running = Arc::new(AtomicUsize::new(0));
+            let peak = Arc::new(AtomicUsize::new(0));
+            let (done, finished) = std::sync::mpsc::channel();
+            for _ in 0..8 {
+                let (running, peak, done) = (running.clone(), peak.clone(), done.clone());
+                spawn(Box::new(move || {
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak.fetch_max(now, Ordering::SeqCst);
+                    std::thread::sleep(Duration::from_millis(50));
+                    running.fetch_sub(1, Ordering::SeqCst);
+                    done.send(()).unwrap();
+                }));
+            }
+            for _ in 0..8 {
+                finished.recv().unwrap();
+            }
+            peak.load(Ordering::SeqCst)
+        }
+
+        let runtime = build_runtime(&config(&[
+            ("TOKIO_WORKERS", "2"),
+            ("TOKIO_BLOCKING_THREADS", "3"),
+        ]))
+        .unwrap();
+        let peak = peak_concurrency(|job| {
+            runtime.spawn(async move { job() });
+        });
+        assert_eq!(peak, 2);
+        let peak = peak_concurrency(|job| {
+            runtime.spawn_blocking(job);
+        });
+        assert_eq!(peak, 3);
+    }
+
+    #[test]
+    fn conversion_panics_are_internal_errors() {
+        let convert = |panic: bool| {
+            catch_conversion_panic(move || {
+                assert!(!panic, "artem broke");
+                "=^.^=".to_owned()
+            })
+        };
+        assert_eq!(convert(false).unwrap(), "=^.^=");
+        let e = convert(true).err().unwrap();
+        assert_eq!(e.to_string(), "Art conversion panicked: artem broke");
+        let response = error_response(&e, &HeaderMap::new(), false);
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[test]
+    fn cat_api_urls_ask_for_the_breed() {
+        let base = reqwest::Url::parse(CAT_API_URL).unwrap();
+        assert_eq!(cat_api_url(&base, None, None), base);
+        assert_eq!(
+            cat_api_url(&base, Some("beng"), None).as_str(),
+            "https://api.thecatapi.com/v1/images/search?breed_ids=beng"
+        );
+        assert_eq!(
+            cat_api_url(&base, Some("a b&c"), Some("7")).as_str(),
+            "https://api.thecatapi.com/v1/images/search?breed_ids=a+b%26c&seed=7"
+        );
+    }
+
+    #[test]
+    fn quota_reset_reads_relative_and_absolute_times() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        assert_eq!(quota_reset_delay(30, now), Duration::from_secs(30));
+        assert_eq!(
+            quota_reset_delay(1_700_000_045, now),
+            Duration::from_secs(45)
+        );
+        assert_eq!(quota_reset_delay(1_699_999_990, now), Duration::ZERO);
+        assert_eq!(quota_reset_delay(1_800_000_000, now), MAX_QUOTA_RESET);
+        assert_eq!(quota_reset_delay(7 * 24 * 60 * 60, now), MAX_QUOTA_RESET);
+    }
+
+    /// A `width`×1 GIF with one frame per color.
+    fn gif(width: u32, colors: &[[u8; 4]]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let mut encoder = image::codecs::gif::GifEncoder::new(&mut bytes);
+        encoder
+            .encode_frames(colors.iter().map(|color| {
+                image::Frame::new(image::RgbaImage::from_pixel(width, 1, image::Rgba(*color)))
+            }))
+            .unwrap();
+        drop(encoder);
+        bytes
+    }
+
+    #[test]
+    fn decodes_the_first_frame_of_animated_gifs() {
+        let bytes = gif(3, &[[255, 0, 0, 255], [0, 0, 255, 255], [0, 255, 0, 255]]);
+        let image = decode_image(&bytes).unwrap().into_rgba8();
+        assert_eq!(image.dimensions(), (3, 1));
+        assert_eq!(image.get_pixel(0, 0), &image::Rgba([255, 0, 0, 255]));
+    }
+
+    /// A lossless 1×1 WebP.
+    const WEBP: &[u8] = &[
+        0x52, 0x49, 0x46, 0x46, 0x1a, 0x00, 0x00, 0x00, 0x57, 0x45, 0x42, 0x50, 0x56, 0x50, 0x38,
+        0x4c, 0x0d, 0x00, 0x00, 0x00, 0x2f, 0x00, 0x00, 0x00, 0x10, 0x07, 0x10, 0x11, 0x11, 0x88,
+        0x88, 0xfe, 0x07, 0x00,
+    ];
+
+    #[test]
+    fn decodes_bmp_tiff_and_ico() {
+        // RGBA, as icons embedding a PNG have to be.
+        let picture =
+            image::DynamicImage::ImageRgba8(image::load_from_memory(&png()).unwrap().to_rgba8());
+        for format in [
+            image::ImageOutputFormat::Bmp,
+            image::ImageOutputFormat::Tiff,
+            image::ImageOutputFormat::Ico,
+        ] {
+            let mut bytes = Vec::new();
+            picture
+                .write_to(&mut std::io::Cursor::new(&mut bytes), format.clone())
+                .unwrap();
+            let image = decode_image(&bytes).unwrap_or_else(|e| panic!("{format:?}: {e:#}"));
+            assert_eq!((image.width(), image.height()), (16, 16), "{format:?}");
+        }
+    }
+
+    #[test]
+    fn decodes_webp() {
+        let image = decode_image(WEBP).unwrap();
+        assert_eq!((image.width(), image.height()), (1, 1));
+    }
+
+    #[test]
+    fn unsupported_formats_are_unsupported_media() {
+        // An AVIF header, which we know but don't decode.
+        let mut avif = b"\x00\x00\x00\x1cftypavif\x00\x00\x00\x00avifmif1miaf".to_vec();
+        avif.resize(64, 0);
+        let e = decode_image(&avif).err().unwrap();
+        assert_eq!(
+            status_for_error(&e),
+            (
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "Image format avif is not supported".to_owned()
+            )
+        );
+
+        let e = decode_image(&[0x42; 64]).err().unwrap();
+        assert_eq!(
+            status_for_error(&e),
+            (
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "Image format not recognized".to_owned()
+            )
+        );
+    }
+
+    #[test]
+    fn broken_gifs_are_errors() {
+        let mut bytes = gif(3, &[[255, 0, 0, 255]]);
+        bytes.truncate(16);
+        assert!(decode_image(&bytes).is_err());
+    }
 }
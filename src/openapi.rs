@@ -0,0 +1,47 @@
+use axum::Json;
+use utoipa::OpenApi;
+
+/// The public routes. Admin routes and the streaming ones aren't described,
+/// OpenAPI has little to say about SSE and WebSockets anyway.
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "catscii", description = "Cat pictures as ASCII art."),
+    paths(
+        crate::root_get,
+        crate::cat_by_id_get,
+        crate::dog_get,
+        crate::random_get,
+        crate::convert_get,
+        crate::cat_png_get,
+        crate::health_get,
+        crate::ready_get,
+        crate::version_get,
+        crate::stats_get,
+    ),
+    components(schemas(
+        crate::Health,
+        crate::HealthDetail,
+        crate::Version,
+        crate::stats::StatsSnapshot,
+        crate::stats::CacheSizes,
+    ))
+)]
+pub struct ApiDoc;
+
+pub async fn openapi_get() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn documents_the_public_routes() {
+        let Json(doc) = openapi_get().await;
+        let doc = serde_json::to_value(doc).unwrap();
+        assert!(doc["paths"]["/"]["get"].is_object(), "{doc}");
+        assert!(doc["paths"]["/cat/{id}"]["get"].is_object(), "{doc}");
+        assert!(doc["paths"].get("/log-level").is_none());
+    }
+}
@@ -0,0 +1,100 @@
+/// Page colors, following the client's light or dark preference.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Theme {
+    #[default]
+    Dark,
+    Light,
+}
+
+impl Theme {
+    /// Parses `?theme=`, or the `Sec-CH-Prefers-Color-Scheme` client hint
+    /// with its quotes stripped.
+    pub fn from_param(raw: &str) -> Result<Self, String> {
+        match raw {
+            "dark" => Ok(Self::Dark),
+            "light" => Ok(Self::Light),
+            _ => Err("theme must be light or dark".to_owned()),
+        }
+    }
+
+    /// Background and text colors.
+    fn colors(self) -> (&'static str, &'static str) {
+        match self {
+            Self::Dark => ("#111", "#eee"),
+            Self::Light => ("#fafafa", "#111"),
+        }
+    }
+}
+
+/// Wraps an HTML art fragment in a standalone page that looks right when
+/// opened straight in a browser.
+pub fn wrap(art: &str, theme: Theme) -> String {
+    let (background, foreground) = theme.colors();
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>catscii</title>
+<style>
+  body {{
+    margin: 0;
+    min-height: 100vh;
+    display: flex;
+    align-items: center;
+    justify-content: center;
+    background: {background};
+    color: {foreground};
+    font-family: ui-monospace, "SFMono-Regular", Menlo, Consolas, monospace;
+  }}
+  /* Browsers disagree on monospace metrics, pin them so the art keeps its
+     proportions everywhere. */
+  main, pre {{
+    font-size: 10px;
+    line-height: 1.1;
+    letter-spacing: 0;
+    font-kerning: none;
+  }}
+  pre {{
+    margin: 0;
+    font-family: inherit;
+  }}
+  main {{
+    display: flex;
+    flex-wrap: wrap;
+    gap: 2em;
+    justify-content: center;
+  }}
+</style>
+</head>
+<body>
+<main>
+{art}
+</main>
+</body>
+</html>
+"#
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn light_pages_are_styled_differently() {
+        let dark = wrap("<pre>cat</pre>", Theme::Dark);
+        let light = wrap("<pre>cat</pre>", Theme::Light);
+        assert_ne!(dark, light);
+        assert!(dark.contains("background: #111;"), "{dark}");
+        assert!(light.contains("background: #fafafa;"), "{light}");
+    }
+
+    #[test]
+    fn parses_themes() {
+        assert_eq!(Theme::from_param("light"), Ok(Theme::Light));
+        assert_eq!(Theme::from_param("dark"), Ok(Theme::Dark));
+        assert!(Theme::from_param("sepia").is_err());
+    }
+}
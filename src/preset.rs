@@ -0,0 +1,86 @@
+use crate::ArtOptions;
+
+/// A named bundle of options, for a look without picking each option.
+pub struct Preset {
+    pub name: &'static str,
+    /// Density ramp, or `None` for artem's own.
+    characters: Option<&'static str>,
+    color: bool,
+    invert: bool,
+}
+
+pub const PRESETS: &[Preset] = &[
+    Preset {
+        name: "classic",
+        characters: None,
+        color: true,
+        invert: false,
+    },
+    Preset {
+        name: "blocks",
+        characters: Some(" ░▒▓█"),
+        color: true,
+        invert: false,
+    },
+    Preset {
+        name: "minimal",
+        characters: Some(" .:"),
+        color: false,
+        invert: false,
+    },
+    Preset {
+        name: "matrix",
+        characters: Some(" .:01"),
+        color: false,
+        invert: true,
+    },
+];
+
+impl Preset {
+    /// Looks up `?preset=`.
+    pub fn from_param(raw: &str) -> Result<&'static Self, String> {
+        PRESETS
+            .iter()
+            .find(|preset| preset.name == raw)
+            .ok_or_else(|| {
+                let names: Vec<_> = PRESETS.iter().map(|preset| preset.name).collect();
+                format!("preset must be one of {}", names.join(", "))
+            })
+    }
+
+    /// `defaults` with this preset's options, which query parameters can
+    /// still override.
+    pub fn apply(&self, defaults: &ArtOptions) -> ArtOptions {
+        ArtOptions {
+            characters: self.characters.map(str::to_owned),
+            color: self.color,
+            invert: self.invert,
+            ..defaults.clone()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn looks_presets_up_by_name() {
+        assert_eq!(Preset::from_param("matrix").unwrap().name, "matrix");
+        let e = Preset::from_param("neon").err().unwrap();
+        assert_eq!(e, "preset must be one of classic, blocks, minimal, matrix");
+    }
+
+    #[test]
+    fn applies_on_top_of_the_defaults() {
+        let defaults = ArtOptions {
+            width: Some(42),
+            ..Default::default()
+        };
+        let options = Preset::from_param("matrix").unwrap().apply(&defaults);
+        assert_eq!(options.characters.as_deref(), Some(" .:01"));
+        assert!(!options.color);
+        assert!(options.invert);
+        assert_eq!(options.width, Some(42));
+    }
+}
@@ -0,0 +1,134 @@
+use axum::{
+    extract::{ConnectInfo, State},
+    http::{header, HeaderValue, Request, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use opentelemetry::trace::TraceId;
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::client_ip::client_ip;
+
+/// Past this many tracked clients, buckets that have fully refilled are
+/// forgotten so the map doesn't grow forever.
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// Per-client token buckets: each client may burst up to `burst` requests,
+/// then gets `rate` more per second.
+pub struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    /// Proxies in front of us we trust, see [`client_ip`].
+    trusted_proxy_hops: usize,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(rate: f64, burst: u32, trusted_proxy_hops: usize) -> Self {
+        Self {
+            rate,
+            burst: burst as f64,
+            trusted_proxy_hops,
+            buckets: Default::default(),
+        }
+    }
+
+    /// Takes a token for `ip`, or returns how long until one is available.
+    pub fn check(&self, ip: IpAddr) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+
+        if buckets.len() > PRUNE_THRESHOLD {
+            buckets.retain(|_, bucket| self.refilled(bucket, now) < self.burst);
+        }
+
+        let bucket = buckets.entry(ip).or_insert(Bucket {
+            tokens: self.burst,
+            updated_at: now,
+        });
+        bucket.tokens = self.refilled(bucket, now);
+        bucket.updated_at = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate))
+        }
+    }
+
+    fn refilled(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.duration_since(bucket.updated_at).as_secs_f64();
+        (bucket.tokens + elapsed * self.rate).min(self.burst)
+    }
+}
+
+/// Middleware answering `429 Too Many Requests` to clients over their limit.
+pub async fn rate_limit<B>(
+    State(limiter): State<Arc<RateLimiter>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let ip = client_ip(request.headers(), peer, limiter.trusted_proxy_hops);
+    match limiter.check(ip) {
+        Ok(()) => next.run(request).await,
+        Err(retry_after) => {
+            let mut response = crate::error_body(
+                StatusCode::TOO_MANY_REQUESTS,
+                "Too many requests, slow down",
+                TraceId::INVALID,
+                request.headers(),
+            );
+            response.headers_mut().insert(
+                header::RETRY_AFTER,
+                HeaderValue::from(retry_after.as_secs_f64().ceil() as u64),
+            );
+            response
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allows_a_burst_then_refuses() {
+        let limiter = RateLimiter::new(1.0, 3, 0);
+        let ip = IpAddr::from([1, 2, 3, 4]);
+        for _ in 0..3 {
+            assert_eq!(limiter.check(ip), Ok(()));
+        }
+        let retry_after = limiter.check(ip).unwrap_err();
+        assert!(retry_after > Duration::ZERO && retry_after <= Duration::from_secs(1));
+    }
+
+    #[test]
+    fn clients_have_their_own_buckets() {
+        let limiter = RateLimiter::new(1.0, 1, 0);
+        assert_eq!(limiter.check(IpAddr::from([1, 2, 3, 4])), Ok(()));
+        assert!(limiter.check(IpAddr::from([1, 2, 3, 4])).is_err());
+        assert_eq!(limiter.check(IpAddr::from([5, 6, 7, 8])), Ok(()));
+    }
+
+    #[test]
+    fn refills_at_the_rate() {
+        let limiter = RateLimiter::new(100.0, 1, 0);
+        let ip = IpAddr::from([1, 2, 3, 4]);
+        assert_eq!(limiter.check(ip), Ok(()));
+        assert!(limiter.check(ip).is_err());
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(limiter.check(ip), Ok(()));
+    }
+}
@@ -0,0 +1,194 @@
+use opentelemetry::{trace::get_active_span, KeyValue};
+use rand::Rng;
+use std::time::Duration;
+
+/// How hard we try to reach an upstream before giving up.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    /// Number of retries after the first attempt.
+    pub max_retries: u32,
+    /// Delay before the first retry.
+    pub base_delay: Duration,
+    /// What the delay is multiplied by for each subsequent retry.
+    pub multiplier: f64,
+    /// Upper bound on the delay before jitter is added.
+    pub max_delay: Duration,
+    /// Add up to 50% random jitter so concurrent requests don't retry in
+    /// lockstep.
+    pub jitter: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            base_delay: Duration::from_millis(200),
+            multiplier: 2.0,
+            max_delay: Duration::from_secs(5),
+            jitter: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Backoff before retry number `attempt` (starting at 1).
+    pub fn delay_for(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(i32::MAX as u32) as i32;
+        // Capped in floating point, where overflowing just means infinity.
+        let exponential = Duration::from_secs_f64(
+            (self.base_delay.as_secs_f64() * self.multiplier.powi(exponent))
+                .min(self.max_delay.as_secs_f64()),
+        );
+        if self.jitter {
+            let jitter = rand::thread_rng().gen_range(0.0..=0.5);
+            exponential.mul_f64(1.0 + jitter)
+        } else {
+            exponential
+        }
+    }
+}
+
+/// Sends the request built by `make_request`, retrying on connection errors
+/// and 5xx responses. Any other error status fails immediately.
+pub async fn send_with_retries(
+    policy: &RetryPolicy,
+    make_request: impl Fn() -> reqwest::RequestBuilder,
+) -> reqwest::Result<reqwest::Response> {
+    let mut attempt = 0;
+    loop {
+        let result = make_request()
+            .send()
+            .await
+            .and_then(|res| res.error_for_status());
+
+        match result {
+            Err(e) if attempt < policy.max_retries && is_retryable(&e) => {
+                attempt += 1;
+                let delay = policy.delay_for(attempt);
+                get_active_span(|span| {
+                    span.add_event(
+                        "retry",
+                        vec![
+                            KeyValue::new("attempt", attempt as i64),
+                            KeyValue::new("delay_ms", delay.as_millis() as i64),
+                            KeyValue::new("error", e.to_string()),
+                        ],
+                    )
+                });
+                tokio::time::sleep(delay).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+fn is_retryable(e: &reqwest::Error) -> bool {
+    match e.status() {
+        Some(status) => status.is_server_error(),
+        None => e.is_connect() || e.is_timeout(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{http::StatusCode, routing::get, Router};
+    use std::{
+        net::SocketAddr,
+        sync::{
+            atomic::{AtomicU32, Ordering},
+            Arc,
+        },
+    };
+
+    /// Serves `statuses` in order, then 200s, counting the requests.
+    fn serve_statuses(statuses: &'static [StatusCode]) -> (SocketAddr, Arc<AtomicU32>) {
+        let requests = Arc::new(AtomicU32::new(0));
+        let handler = {
+            let requests = requests.clone();
+            move || async move {
+                let index = requests.fetch_add(1, Ordering::SeqCst) as usize;
+                statuses.get(index).copied().unwrap_or(StatusCode::OK)
+            }
+        };
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(Router::new().route("/", get(handler)).into_make_service());
+        tokio::spawn(server);
+        (addr, requests)
+    }
+
+    async fn send(addr: SocketAddr) -> reqwest::Result<reqwest::Response> {
+        let client = reqwest::Client::new();
+        let policy = RetryPolicy {
+            max_retries: 2,
+            base_delay: Duration::from_millis(1),
+            ..policy(false)
+        };
+        send_with_retries(&policy, || client.get(format!("http://{addr}/"))).await
+    }
+
+    #[tokio::test]
+    async fn retries_server_errors() {
+        let (addr, requests) = serve_statuses(&[
+            StatusCode::INTERNAL_SERVER_ERROR,
+            StatusCode::SERVICE_UNAVAILABLE,
+        ]);
+        assert!(send(addr).await.is_ok());
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_retries() {
+        let (addr, requests) = serve_statuses(&[StatusCode::BAD_GATEWAY; 5]);
+        let e = send(addr).await.unwrap_err();
+        assert_eq!(e.status(), Some(StatusCode::BAD_GATEWAY));
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn client_errors_fail_immediately() {
+        let (addr, requests) = serve_statuses(&[StatusCode::NOT_FOUND]);
+        let e = send(addr).await.unwrap_err();
+        assert_eq!(e.status(), Some(StatusCode::NOT_FOUND));
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    fn policy(jitter: bool) -> RetryPolicy {
+        RetryPolicy {
+            max_retries: 10,
+            base_delay: Duration::from_millis(100),
+            multiplier: 2.0,
+            max_delay: Duration::from_secs(1),
+            jitter,
+        }
+    }
+
+    #[test]
+    fn delays_grow_exponentially_up_to_the_cap() {
+        let policy = policy(false);
+        assert_eq!(policy.delay_for(1), Duration::from_millis(100));
+        assert_eq!(policy.delay_for(2), Duration::from_millis(200));
+        assert_eq!(policy.delay_for(4), Duration::from_millis(800));
+        assert_eq!(policy.delay_for(5), Duration::from_secs(1));
+        assert_eq!(policy.delay_for(u32::MAX), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn jitter_adds_up_to_half() {
+        let policy = policy(true);
+        for attempt in [1, 3, 20] {
+            let plain = RetryPolicy {
+                jitter: false,
+                ..policy
+            }
+            .delay_for(attempt);
+            for _ in 0..100 {
+                let delay = policy.delay_for(attempt);
+                assert!(delay >= plain && delay <= plain.mul_f64(1.5), "{delay:?}");
+            }
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use opentelemetry::trace::{SamplingDecision, SamplingResult};
+use std::{collections::HashMap, sync::OnceLock};
+
+/// Fraction of requests traced, by request span name. Global like the tracer
+/// it goes with. Names missing from it are always traced.
+static RATES: OnceLock<HashMap<String, f64>> = OnceLock::new();
+
+/// Sets the rates from `$TRACE_SAMPLE_RATES`, once at startup.
+pub fn set_rates(rates: HashMap<String, f64>) {
+    _ = RATES.set(rates);
+}
+
+/// Decides whether a new trace rooted at span `name` is kept, or `None` to
+/// leave it to the tracer.
+pub fn decide(name: &str) -> Option<SamplingResult> {
+    let rate = *RATES.get()?.get(name)?;
+    let sampled = rand::random::<f64>() < rate;
+    Some(SamplingResult {
+        decision: if sampled {
+            SamplingDecision::RecordAndSample
+        } else {
+            SamplingDecision::Drop
+        },
+        attributes: Vec::new(),
+        trace_state: Default::default(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::future::BoxFuture;
+    use opentelemetry::{
+        sdk::{
+            export::trace::{ExportResult, SpanData, SpanExporter},
+            trace::TracerProvider,
+        },
+        trace::{Span, Tracer, TracerProvider as _},
+    };
+    use std::sync::{Arc, Mutex};
+
+    /// Keeps the names of the spans that would have gone to Honeycomb.
+    #[derive(Debug, Default, Clone)]
+    struct Exported(Arc<Mutex<Vec<String>>>);
+
+    impl SpanExporter for Exported {
+        fn export(&mut self, batch: Vec<SpanData>) -> BoxFuture<'static, ExportResult> {
+            let mut names = self.0.lock().unwrap();
+            names.extend(batch.into_iter().map(|span| span.name.into_owned()));
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    #[test]
+    fn sampled_out_routes_emit_no_root_span() {
+        set_rates(HashMap::from([
+            ("dog_get".to_owned(), 0.0),
+            ("cat_by_id_get".to_owned(), 1.0),
+        ]));
+        assert!(decide("root_get").is_none());
+
+        let exported = Exported::default();
+        let provider = TracerProvider::builder()
+            .with_simple_exporter(exported.clone())
+            .build();
+        let tracer = provider.tracer("");
+        for name in ["root_get", "dog_get", "cat_by_id_get"] {
+            let mut builder = tracer.span_builder(name);
+            builder.sampling_result = decide(name);
+            tracer.build(builder).end();
+        }
+        // Waits for the exporter to finish.
+        drop(tracer);
+        drop(provider);
+
+        assert_eq!(*exported.0.lock().unwrap(), ["root_get", "cat_by_id_get"]);
+    }
+}
@@ -0,0 +1,150 @@
+use async_trait::async_trait;
+use serde::Deserialize;
+
+use crate::{get_cat_image_url, get_cat_image_url_by_id, retry::send_with_retries, ServerState};
+
+/// Somewhere that hands out URLs of animal pictures. Everything after the
+/// URL (download, decode, convert) is shared between sources.
+#[async_trait]
+pub trait AnimalSource: Send + Sync {
+    /// Short lowercase name, used for span names and attributes.
+    fn name(&self) -> &'static str;
+
+    async fn image_url(&self, state: &ServerState) -> color_eyre::Result<String>;
+
+    /// What to download the image with.
+    fn client<'a>(&self, state: &'a ServerState) -> &'a reqwest::Client {
+        &state.client
+    }
+
+    /// Whether conversions go to `$ARCHIVE_DIR`, which is only meant for
+    /// cats, not whatever anyone sends to `/convert`.
+    fn archived(&self) -> bool {
+        false
+    }
+}
+
+/// Where [`CatSource`] gets its cats, swappable so handlers can be exercised
+/// without The Cat API.
+#[async_trait]
+pub trait CatImageProvider: Send + Sync {
+    async fn random_image_url(
+        &self,
+        state: &ServerState,
+        breed: Option<&str>,
+        seed: Option<&str>,
+    ) -> color_eyre::Result<String>;
+
+    async fn image_url_by_id(&self, state: &ServerState, id: &str) -> color_eyre::Result<String>;
+}
+
+/// The real thing, over HTTP.
+pub struct CatApi;
+
+#[async_trait]
+impl CatImageProvider for CatApi {
+    async fn random_image_url(
+        &self,
+        state: &ServerState,
+        breed: Option<&str>,
+        seed: Option<&str>,
+    ) -> color_eyre::Result<String> {
+        get_cat_image_url(state, breed, seed).await
+    }
+
+    async fn image_url_by_id(&self, state: &ServerState, id: &str) -> color_eyre::Result<String> {
+        get_cat_image_url_by_id(state, id).await
+    }
+}
+
+/// Random cats, optionally of a single breed.
+#[derive(Default)]
+pub struct CatSource {
+    pub breed: Option<String>,
+    /// Passed through to the Cat API so the same seed picks the same cat.
+    pub seed: Option<String>,
+}
+
+#[async_trait]
+impl AnimalSource for CatSource {
+    fn name(&self) -> &'static str {
+        "cat"
+    }
+
+    fn archived(&self) -> bool {
+        true
+    }
+
+    async fn image_url(&self, state: &ServerState) -> color_eyre::Result<String> {
+        state
+            .cat_images
+            .random_image_url(state, self.breed.as_deref(), self.seed.as_deref())
+            .await
+    }
+}
+
+/// The Cat API image with a given id.
+pub struct CatByIdSource {
+    pub id: String,
+}
+
+#[async_trait]
+impl AnimalSource for CatByIdSource {
+    fn name(&self) -> &'static str {
+        "cat_by_id"
+    }
+
+    fn archived(&self) -> bool {
+        true
+    }
+
+    async fn image_url(&self, state: &ServerState) -> color_eyre::Result<String> {
+        state.cat_images.image_url_by_id(state, &self.id).await
+    }
+}
+
+/// A single, already known image.
+pub struct UrlSource {
+    pub url: reqwest::Url,
+}
+
+#[async_trait]
+impl AnimalSource for UrlSource {
+    fn name(&self) -> &'static str {
+        "url"
+    }
+
+    async fn image_url(&self, _state: &ServerState) -> color_eyre::Result<String> {
+        Ok(self.url.to_string())
+    }
+
+    fn client<'a>(&self, state: &'a ServerState) -> &'a reqwest::Client {
+        &state.convert_client
+    }
+}
+
+pub const DOG_API_URL: &str = "https://dog.ceo/api/breeds/image/random";
+
+/// Random dogs from dog.ceo.
+pub struct DogSource;
+
+#[async_trait]
+impl AnimalSource for DogSource {
+    fn name(&self) -> &'static str {
+        "dog"
+    }
+
+    async fn image_url(&self, state: &ServerState) -> color_eyre::Result<String> {
+        #[derive(Deserialize)]
+        struct DogImage {
+            message: String,
+        }
+
+        let image = send_with_retries(&state.retry, || state.client.get(state.dog_api_url.clone()))
+            .await?
+            .json::<DogImage>()
+            .await?;
+
+        Ok(image.message)
+    }
+}
@@ -0,0 +1,47 @@
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Running totals since startup, for a quick look without Prometheus.
+#[derive(Default)]
+pub struct Stats {
+    pub requests: AtomicU64,
+    pub successes: AtomicU64,
+    pub upstream_failures: AtomicU64,
+    pub cache_hits: AtomicU64,
+}
+
+#[derive(Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct StatsSnapshot {
+    requests: u64,
+    successes: u64,
+    upstream_failures: u64,
+    cache_hits: u64,
+    #[serde(flatten)]
+    caches: CacheSizes,
+}
+
+/// How full the caches are right now.
+#[derive(Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct CacheSizes {
+    pub art_cache_entries: usize,
+    pub download_cache_entries: usize,
+    pub download_cache_bytes: usize,
+}
+
+impl Stats {
+    pub fn increment(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self, caches: CacheSizes) -> StatsSnapshot {
+        StatsSnapshot {
+            requests: self.requests.load(Ordering::Relaxed),
+            successes: self.successes.load(Ordering::Relaxed),
+            upstream_failures: self.upstream_failures.load(Ordering::Relaxed),
+            cache_hits: self.cache_hits.load(Ordering::Relaxed),
+            caches,
+        }
+    }
+}